@@ -33,6 +33,7 @@ pub enum CheckErrors {
 
     ValueTooLarge,
     ValueOutOfBounds,
+    NoSerializedSizeBound(TypeSignature),
     TypeSignatureTooDeep,
     ExpectedName,
 
@@ -81,6 +82,7 @@ pub enum CheckErrors {
     BadTokenName,
     DefineFTBadSignature,
     DefineNFTBadSignature,
+    DefineNFTBadKeyType(TypeSignature),
     NoSuchNFT(String),
     NoSuchFT(String),
 
@@ -127,6 +129,7 @@ pub enum CheckErrors {
     GetBlockInfoExpectPropertyName,
 
     NameAlreadyUsed(String),
+    ReservedName(String),
 
     // expect a function, or applying a function to a list
     NonFunctionApplication,
@@ -322,6 +325,7 @@ impl DiagnosableError for CheckErrors {
             CheckErrors::UnknownTypeName(name) => format!("failed to parse type: '{}'", name),
             CheckErrors::ValueTooLarge => format!("created a type which was greater than maximum allowed value size"),
             CheckErrors::ValueOutOfBounds => format!("created a type which value size was out of defined bounds"),
+            CheckErrors::NoSerializedSizeBound(found_type) => format!("values of type '{}' have no serialized size bound", found_type),
             CheckErrors::TypeSignatureTooDeep => "created a type which was deeper than maximum allowed type depth".into(),
             CheckErrors::ExpectedName => format!("expected a name argument to this function"),
             CheckErrors::NoSuperType(a, b) => format!("unable to create a supertype for the two types: '{}' and '{}'", a, b),
@@ -369,6 +373,7 @@ impl DiagnosableError for CheckErrors {
             CheckErrors::NoSuchBlockInfoProperty(property_name) => format!("use of block unknown property '{}'", property_name),
             CheckErrors::GetBlockInfoExpectPropertyName => format!("missing property name for block info introspection"),
             CheckErrors::NameAlreadyUsed(name) => format!("defining '{}' conflicts with previous value", name),
+            CheckErrors::ReservedName(name) => format!("'{}' is a reserved keyword and cannot be used as a name", name),
             CheckErrors::NonFunctionApplication => format!("expecting expression of type function"),
             CheckErrors::ExpectedListApplication => format!("expecting expression of type list"),
             CheckErrors::ExpectedSequence(found_type) => format!("expecting expression of type 'list', 'buff', 'string-ascii' or 'string-utf8' - found '{}'", found_type),
@@ -393,6 +398,7 @@ impl DiagnosableError for CheckErrors {
             CheckErrors::BadTokenName => format!("expecting an token name as an argument"),
             CheckErrors::DefineFTBadSignature => format!("(define-token ...) expects a token name as an argument"),
             CheckErrors::DefineNFTBadSignature => format!("(define-asset ...) expects an asset name and an asset identifier type signature as arguments"),
+            CheckErrors::DefineNFTBadKeyType(key_type) => format!("(define-non-fungible-token ...) expects an asset identifier type of comparable data, found '{}'", key_type),
             CheckErrors::NoSuchNFT(asset_name) => format!("tried to use asset function with a undefined asset ('{}')", asset_name),
             CheckErrors::NoSuchFT(asset_name) => format!("tried to use token function with a undefined token ('{}')", asset_name),
             CheckErrors::NoSuchTrait(contract_name, trait_name) => format!("use of unresolved trait {}.{}", contract_name, trait_name),
@@ -43,9 +43,19 @@ use self::type_checker::TypeChecker;
 
 /// Used by CLI tools like the docs generator. Not used in production
 pub fn mem_type_check(snippet: &str) -> CheckResult<(Option<TypeSignature>, ContractAnalysis)> {
-    use crate::vm::ast::parse;
+    mem_type_check_in_epoch(snippet, StacksEpochId::Epoch2_05)
+}
+
+/// Like `mem_type_check`, but parses and checks `snippet` with the rules of `epoch`
+pub fn mem_type_check_in_epoch(
+    snippet: &str,
+    epoch: StacksEpochId,
+) -> CheckResult<(Option<TypeSignature>, ContractAnalysis)> {
+    use crate::vm::ast::build_ast_in_epoch;
     let contract_identifier = QualifiedContractIdentifier::transient();
-    let mut contract = parse(&contract_identifier, snippet).unwrap();
+    let mut contract = build_ast_in_epoch(&contract_identifier, snippet, &mut (), epoch)
+        .unwrap()
+        .expressions;
     let mut marf = MemoryBackingStore::new();
     let mut analysis_db = marf.as_analysis_db();
     let cost_tracker = LimitedCostTracker::new_free();
@@ -55,6 +65,7 @@ pub fn mem_type_check(snippet: &str) -> CheckResult<(Option<TypeSignature>, Cont
         &mut analysis_db,
         false,
         cost_tracker,
+        epoch,
     ) {
         Ok(x) => {
             // return the first type result of the type checker
@@ -87,6 +98,7 @@ pub fn type_check(
         // for the type check tests, the cost tracker's epoch doesn't
         //  matter: the costs in those tests are all free anyways.
         LimitedCostTracker::new_free(),
+        StacksEpochId::Epoch2_05,
     )
     .map_err(|(e, _cost_tracker)| e)
 }
@@ -97,6 +109,7 @@ pub fn run_analysis(
     analysis_db: &mut AnalysisDatabase,
     save_contract: bool,
     cost_tracker: LimitedCostTracker,
+    epoch: StacksEpochId,
 ) -> Result<ContractAnalysis, (CheckError, LimitedCostTracker)> {
     let mut contract_analysis = ContractAnalysis::new(
         contract_identifier.clone(),
@@ -105,7 +118,7 @@ pub fn run_analysis(
     );
     let result = analysis_db.execute(|db| {
         ReadOnlyChecker::run_pass(&mut contract_analysis, db)?;
-        TypeChecker::run_pass(&mut contract_analysis, db)?;
+        TypeChecker::run_pass_in_epoch(&mut contract_analysis, db, epoch)?;
         TraitChecker::run_pass(&mut contract_analysis, db)?;
         ArithmeticOnlyChecker::check_contract_cost_eligible(&mut contract_analysis);
 
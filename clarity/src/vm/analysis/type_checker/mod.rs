@@ -18,23 +18,25 @@ pub mod contexts;
 //mod maps;
 pub mod natives;
 
+use crate::types::StacksEpochId;
 use crate::vm::costs::{
     analysis_typecheck_cost, cost_functions, runtime_cost, ClarityCostFunctionReference,
     CostErrors, CostOverflowingMath, CostTracker, ExecutionCost, LimitedCostTracker,
 };
 use crate::vm::functions::define::DefineFunctionsParsed;
 use crate::vm::functions::NativeFunctions;
+use crate::vm::is_reserved;
 use crate::vm::representations::SymbolicExpressionType::{
     Atom, AtomValue, Field, List, LiteralValue, TraitReference,
 };
 use crate::vm::representations::{depth_traverse, ClarityName, SymbolicExpression};
 use crate::vm::types::signatures::{FunctionSignature, BUFF_20};
 use crate::vm::types::{
-    parse_name_type_pairs, FixedFunction, FunctionArg, FunctionType, PrincipalData,
+    parse_name_type_pairs_located, FixedFunction, FunctionArg, FunctionType, PrincipalData,
     QualifiedContractIdentifier, TupleTypeSignature, TypeSignature, Value,
 };
 use crate::vm::variables::NativeVariables;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::convert::TryInto;
 
 pub use super::types::{AnalysisPass, ContractAnalysis};
@@ -75,6 +77,7 @@ pub struct TypeChecker<'a, 'b> {
     function_return_tracker: Option<Option<TypeSignature>>,
     db: &'a mut AnalysisDatabase<'b>,
     pub cost_track: LimitedCostTracker,
+    epoch: StacksEpochId,
 }
 
 impl CostTracker for TypeChecker<'_, '_> {
@@ -110,12 +113,23 @@ impl CostTracker for TypeChecker<'_, '_> {
 }
 
 impl AnalysisPass for TypeChecker<'_, '_> {
+    /// Legacy function: type check with the rules of Stacks 2.05
     fn run_pass(
         contract_analysis: &mut ContractAnalysis,
         analysis_db: &mut AnalysisDatabase,
+    ) -> CheckResult<()> {
+        TypeChecker::run_pass_in_epoch(contract_analysis, analysis_db, StacksEpochId::Epoch2_05)
+    }
+}
+
+impl TypeChecker<'_, '_> {
+    pub fn run_pass_in_epoch(
+        contract_analysis: &mut ContractAnalysis,
+        analysis_db: &mut AnalysisDatabase,
+        epoch: StacksEpochId,
     ) -> CheckResult<()> {
         let cost_track = contract_analysis.take_contract_cost_tracker();
-        let mut command = TypeChecker::new(analysis_db, cost_track);
+        let mut command = TypeChecker::new(analysis_db, cost_track, epoch);
         // run the analysis, and replace the cost tracker whether or not the
         //   analysis succeeded.
         match command.run(contract_analysis) {
@@ -311,14 +325,41 @@ pub fn no_type() -> TypeSignature {
     TypeSignature::NoType
 }
 
+/// Reject a define form whose defined name is a reserved keyword, pointing
+///  the diagnostic at the name itself rather than the whole define.
+fn check_define_name_not_reserved(expression: &SymbolicExpression) -> CheckResult<()> {
+    let name_expr = match expression.match_list().and_then(|list| list.get(1)) {
+        Some(name_expr) => name_expr,
+        None => return Ok(()),
+    };
+    // function signatures wrap the name: (define-public (name (arg type) ...) ...)
+    let name_expr = match name_expr.match_list() {
+        Some(signature) => match signature.get(0) {
+            Some(name_expr) => name_expr,
+            None => return Ok(()),
+        },
+        None => name_expr,
+    };
+    match name_expr.match_atom() {
+        Some(name) if is_reserved(name) => {
+            let mut error = CheckError::new(CheckErrors::ReservedName(name.to_string()));
+            error.set_expression(name_expr);
+            Err(error)
+        }
+        _ => Ok(()),
+    }
+}
+
 impl<'a, 'b> TypeChecker<'a, 'b> {
     fn new(
         db: &'a mut AnalysisDatabase<'b>,
         cost_track: LimitedCostTracker,
+        epoch: StacksEpochId,
     ) -> TypeChecker<'a, 'b> {
         Self {
             db,
             cost_track,
+            epoch,
             contract_context: ContractContext::new(),
             function_return_tracker: None,
             type_map: TypeMap::new(),
@@ -517,14 +558,31 @@ impl<'a, 'b> TypeChecker<'a, 'b> {
         body: &SymbolicExpression,
         context: &TypingContext,
     ) -> CheckResult<(ClarityName, FixedFunction)> {
-        let (function_name, args) = signature
+        let (function_name, arg_symbols) = signature
             .split_first()
             .ok_or(CheckErrors::RequiresAtLeastArguments(1, 0))?;
         let function_name = function_name
             .match_atom()
             .ok_or(CheckErrors::BadFunctionName)?;
-        let mut args = parse_name_type_pairs::<()>(args, &mut ())
-            .map_err(|_| CheckErrors::BadSyntaxBinding)?;
+        let mut args = parse_name_type_pairs_located::<()>(arg_symbols, &mut ()).map_err(|e| {
+            let mut err = CheckError::new(CheckErrors::BadSyntaxBinding);
+            err.diagnostic.spans = e.diagnostic.spans;
+            err.expressions = e.expressions;
+            err
+        })?;
+
+        // before 2.1, a duplicate argument name is only rejected when the function is called
+        if self.epoch >= StacksEpochId::Epoch21 {
+            let mut arg_names = HashSet::new();
+            for ((arg_name, _), arg_symbol) in args.iter().zip(arg_symbols.iter()) {
+                if !arg_names.insert(arg_name) {
+                    let mut err =
+                        CheckError::new(CheckErrors::NameAlreadyUsed(arg_name.to_string()));
+                    err.set_expression(arg_symbol);
+                    return Err(err);
+                }
+            }
+        }
 
         if self.function_return_tracker.is_some() {
             panic!("Interpreter error: Previous function define left dirty typecheck state.");
@@ -743,6 +801,9 @@ impl<'a, 'b> TypeChecker<'a, 'b> {
     ) -> CheckResult<(ClarityName, TypeSignature)> {
         let asset_type = TypeSignature::parse_type_repr::<()>(&nft_type, &mut ())
             .or_else(|_| Err(CheckErrors::DefineNFTBadSignature))?;
+        if self.epoch >= StacksEpochId::Epoch21 && !asset_type.is_valid_nft_key_type() {
+            return Err(CheckErrors::DefineNFTBadKeyType(asset_type).into());
+        }
 
         Ok((asset_name.clone(), asset_type))
     }
@@ -753,7 +814,8 @@ impl<'a, 'b> TypeChecker<'a, 'b> {
         function_types: &[SymbolicExpression],
         _context: &mut TypingContext,
     ) -> CheckResult<(ClarityName, BTreeMap<ClarityName, FunctionSignature>)> {
-        let trait_signature = TypeSignature::parse_trait_type_repr(&function_types, &mut ())?;
+        let trait_signature =
+            TypeSignature::parse_trait_type_repr_located(&function_types, &mut ())?;
 
         Ok((trait_name.clone(), trait_signature))
     }
@@ -765,6 +827,10 @@ impl<'a, 'b> TypeChecker<'a, 'b> {
         context: &mut TypingContext,
     ) -> CheckResult<Option<()>> {
         if let Some(define_type) = DefineFunctionsParsed::try_parse(expression)? {
+            // before 2.1, reserved names are only rejected when the contract is initialized
+            if self.epoch >= StacksEpochId::Epoch21 {
+                check_define_name_not_reserved(expression)?;
+            }
             match define_type {
                 DefineFunctionsParsed::Constant { name, value } => {
                     let (v_name, v_type) = self.type_check_define_variable(name, value, context)?;
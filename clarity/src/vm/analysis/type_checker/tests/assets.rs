@@ -14,8 +14,9 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::types::StacksEpochId;
 use crate::vm::analysis::errors::CheckErrors;
-use crate::vm::analysis::{mem_type_check, AnalysisDatabase};
+use crate::vm::analysis::{mem_type_check, mem_type_check_in_epoch, AnalysisDatabase};
 use crate::vm::ast::parse;
 use crate::vm::database::MemoryBackingStore;
 use crate::vm::types::{
@@ -216,3 +217,31 @@ fn test_bad_asset_usage() {
         assert_eq!(&actual_err.err, expected_err);
     }
 }
+
+#[test]
+fn test_nft_key_types() {
+    let type_check_21 = |snippet| mem_type_check_in_epoch(snippet, StacksEpochId::Epoch21);
+
+    type_check_21("(define-non-fungible-token names uint)").unwrap();
+    type_check_21("(define-non-fungible-token names (list 4 (string-ascii 10)))").unwrap();
+
+    let err = type_check_21("(define-non-fungible-token names (response uint int))").unwrap_err();
+    assert_eq!(
+        err.err,
+        CheckErrors::DefineNFTBadKeyType(
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::IntType).unwrap()
+        )
+    );
+
+    let err = type_check_21(
+        "(define-non-fungible-token names { id: uint, result: (response bool bool) })",
+    )
+    .unwrap_err();
+    assert!(match err.err {
+        CheckErrors::DefineNFTBadKeyType(TypeSignature::TupleType(_)) => true,
+        _ => false,
+    });
+
+    // before 2.1, any identifier type is accepted
+    mem_type_check("(define-non-fungible-token names (response uint int))").unwrap();
+}
@@ -14,8 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::types::StacksEpochId;
 use crate::vm::analysis::errors::CheckErrors;
 use crate::vm::analysis::mem_type_check;
+use crate::vm::analysis::mem_type_check_in_epoch;
 use crate::vm::analysis::type_check;
 use crate::vm::analysis::type_checker::{TypeChecker, TypeResult, TypingContext};
 use crate::vm::analysis::types::ContractAnalysis;
@@ -1513,6 +1515,39 @@ fn test_mismatching_type_on_declaration_should_fail() {
     });
 }
 
+#[test]
+fn test_reserved_name_on_declaration_should_fail() {
+    // before 2.1, reserved names are left for the runtime to reject
+    mem_type_check("(define-constant block-height u1)").unwrap();
+    mem_type_check("(define-data-var is-ok bool true)").unwrap();
+
+    let res = mem_type_check_in_epoch("(define-constant block-height u1)", StacksEpochId::Epoch21)
+        .unwrap_err();
+    assert_eq!(
+        res.err,
+        CheckErrors::ReservedName("block-height".to_string())
+    );
+    let span = &res.diagnostic.spans[0];
+    assert_eq!(
+        (span.start_line, span.start_column, span.end_column),
+        (1, 18, 29)
+    );
+
+    let res = mem_type_check_in_epoch("(define-read-only (true) (ok u1))", StacksEpochId::Epoch21)
+        .unwrap_err();
+    assert_eq!(res.err, CheckErrors::ReservedName("true".to_string()));
+    let res = mem_type_check_in_epoch("(define-data-var is-ok bool true)", StacksEpochId::Epoch21)
+        .unwrap_err();
+    assert_eq!(res.err, CheckErrors::ReservedName("is-ok".to_string()));
+
+    let contract_src = r#"
+        (define-constant height u1)
+        (define-data-var height uint u2)
+    "#;
+    let res = mem_type_check_in_epoch(contract_src, StacksEpochId::Epoch21).unwrap_err();
+    assert_eq!(res.err, CheckErrors::NameAlreadyUsed("height".to_string()));
+}
+
 #[test]
 fn test_mismatching_type_on_update_should_fail() {
     let contract_src = r#"
@@ -2225,3 +2260,83 @@ fn test_string_utf8_negative_len() {
         _ => false,
     });
 }
+
+#[test]
+fn test_malformed_function_arg_pair_span() {
+    let res = mem_type_check("(define-public (foo (a uint) (amount)) (ok u1))").unwrap_err();
+    assert_eq!(res.err, CheckErrors::BadSyntaxBinding);
+    let span = &res.diagnostic.spans[0];
+    assert_eq!(
+        (span.start_line, span.start_column, span.end_column),
+        (1, 30, 37)
+    );
+
+    let res = mem_type_check("(define-private (foo (u1 uint)) u1)").unwrap_err();
+    assert_eq!(res.err, CheckErrors::BadSyntaxBinding);
+    let span = &res.diagnostic.spans[0];
+    assert_eq!(
+        (span.start_line, span.start_column, span.end_column),
+        (1, 22, 30)
+    );
+
+    // every pair's structure is checked before any type is parsed, so a malformed later pair
+    //  is reported even when an earlier pair's type is bad
+    let pairs = parse(
+        &QualifiedContractIdentifier::transient(),
+        "((a (buff)) (b))",
+    )
+    .unwrap();
+    let err = crate::vm::types::parse_name_type_pairs_located::<()>(
+        pairs[0].match_list().unwrap(),
+        &mut (),
+    )
+    .unwrap_err();
+    assert_eq!(err.err, CheckErrors::BadSyntaxExpectedListOfPairs);
+    let span = &err.diagnostic.spans[0];
+    assert_eq!((span.start_column, span.end_column), (13, 15));
+}
+
+#[test]
+fn test_define_trait_method_signatures() {
+    let contract_src = "(define-trait token ((transfer? (uint (tuple (to principal) (memo (optional (buff 34))))) (response uint uint))))";
+    let (_, analysis) = mem_type_check(contract_src).unwrap();
+    let token = analysis.get_defined_trait("token").unwrap();
+    let transfer = token.get("transfer?").unwrap();
+    assert_eq!(
+        transfer.returns,
+        TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap()
+    );
+    assert_eq!(transfer.args.len(), 2);
+    assert_eq!(transfer.args[0], TypeSignature::UIntType);
+
+    // the error points at the malformed method, not the whole define-trait
+    let contract_src = "(define-trait token ((get-supply () (response uint uint)) (transfer? (uint) (response uint))))";
+    let res = mem_type_check(contract_src).unwrap_err();
+    assert_eq!(res.err, CheckErrors::DefineTraitBadSignature);
+    let span = &res.diagnostic.spans[0];
+    assert_eq!(
+        (span.start_line, span.start_column, span.end_column),
+        (1, 59, 92)
+    );
+}
+
+#[test]
+fn test_duplicate_argument_names_should_fail() {
+    let duplicated = "(define-private (f (x int) (y int) (x int)) x)";
+    // before 2.1, the analysis accepts it and the call fails instead
+    mem_type_check(duplicated).unwrap();
+
+    let res = mem_type_check_in_epoch(duplicated, StacksEpochId::Epoch21).unwrap_err();
+    assert_eq!(res.err, CheckErrors::NameAlreadyUsed("x".to_string()));
+    let span = &res.diagnostic.spans[0];
+    assert_eq!(
+        (span.start_line, span.start_column, span.end_column),
+        (1, 36, 42)
+    );
+
+    mem_type_check_in_epoch(
+        "(define-private (f (x int) (y int)) (+ x y))",
+        StacksEpochId::Epoch21,
+    )
+    .unwrap();
+}
@@ -0,0 +1,166 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::vm::callables::CallableType;
+use crate::vm::errors::{check_arguments_at_least, CheckErrors, InterpreterResult as Result};
+use crate::vm::functions::{lookup_reserved_functions, NativeFunctions};
+use crate::vm::representations::SymbolicExpression;
+use crate::vm::representations::SymbolicExpressionType::{Atom, AtomValue, List, LiteralValue};
+use crate::vm::types::{TypeSignature, Value};
+
+/// Evaluate `expr` at analysis time, if it is a constant expression: a literal, or a pure
+///  arithmetic or boolean function applied to constant expressions.  Returns `None` for
+///  anything else (e.g., an expression that reads state, calls a user function or refers to a
+///  variable), and also if evaluating the expression fails (see `try_const_fold`).
+pub fn const_fold(expr: &SymbolicExpression) -> Option<Value> {
+    try_const_fold(expr).and_then(|result| result.ok())
+}
+
+/// Like `const_fold`, but a constant expression whose evaluation fails (e.g., with an
+///  arithmetic overflow or a division by zero) returns the error that evaluating it at runtime
+///  would.
+pub fn try_const_fold(expr: &SymbolicExpression) -> Option<Result<Value>> {
+    match expr.expr {
+        LiteralValue(ref value) | AtomValue(ref value) => Some(Ok(value.clone())),
+        Atom(ref name) => match name.as_str() {
+            "true" => Some(Ok(Value::Bool(true))),
+            "false" => Some(Ok(Value::Bool(false))),
+            _ => None,
+        },
+        List(ref list) => {
+            let (function_name, args) = list.split_first()?;
+            let function = NativeFunctions::lookup_by_name(function_name.match_atom()?)?;
+            try_fold_application(function, args)
+        }
+        _ => None,
+    }
+}
+
+fn try_fold_application(
+    function: NativeFunctions,
+    args: &[SymbolicExpression],
+) -> Option<Result<Value>> {
+    use crate::vm::functions::NativeFunctions::*;
+    match function {
+        Add | Subtract | Multiply | Divide | CmpGeq | CmpLeq | CmpLess | CmpGreater | ToUInt
+        | ToInt | Modulo | Power | Sqrti | Log2 | BitwiseXOR | Not | Equals => {}
+        And => return try_fold_boolean(true, args),
+        Or => return try_fold_boolean(false, args),
+        _ => return None,
+    }
+
+    // every argument must be constant, even if an earlier one fails to evaluate
+    let folded_args = args
+        .iter()
+        .map(try_const_fold)
+        .collect::<Option<Vec<_>>>()?;
+    let evaluated_args = match folded_args.into_iter().collect::<Result<Vec<_>>>() {
+        Ok(evaluated_args) => evaluated_args,
+        Err(e) => return Some(Err(e)),
+    };
+    match lookup_reserved_functions(function.get_name_str())? {
+        CallableType::NativeFunction(_, handle, _)
+        | CallableType::NativeFunction205(_, handle, _, _) => Some(handle.apply(evaluated_args)),
+        _ => None,
+    }
+}
+
+/// `and` and `or` short-circuit at runtime, so fold their arguments in order, and stop at the
+///  first one that decides the result.
+fn try_fold_boolean(is_and: bool, args: &[SymbolicExpression]) -> Option<Result<Value>> {
+    if let Err(e) = check_arguments_at_least(1, args) {
+        return Some(Err(e.into()));
+    }
+    for arg in args.iter() {
+        let value = match try_const_fold(arg)? {
+            Ok(Value::Bool(value)) => value,
+            Ok(value) => {
+                return Some(Err(CheckErrors::TypeValueError(
+                    TypeSignature::BoolType,
+                    value,
+                )
+                .into()))
+            }
+            Err(e) => return Some(Err(e)),
+        };
+        if value != is_and {
+            return Some(Ok(Value::Bool(value)));
+        }
+    }
+    Some(Ok(Value::Bool(is_and)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{const_fold, try_const_fold};
+    use crate::vm::ast::build_ast;
+    use crate::vm::errors::{Error, RuntimeErrorType};
+    use crate::vm::representations::SymbolicExpression;
+    use crate::vm::types::{QualifiedContractIdentifier, Value};
+
+    fn parse_expr(source: &str) -> SymbolicExpression {
+        build_ast(&QualifiedContractIdentifier::transient(), source, &mut ())
+            .unwrap()
+            .expressions
+            .remove(0)
+    }
+
+    #[test]
+    fn test_const_fold() {
+        assert_eq!(
+            const_fold(&parse_expr("(* u1000000 u1000000)")),
+            Some(Value::UInt(1_000_000_000_000))
+        );
+        assert_eq!(
+            const_fold(&parse_expr("(- (pow 2 10) (/ 7 2))")),
+            Some(Value::Int(1021))
+        );
+        assert_eq!(
+            const_fold(&parse_expr(
+                "(and (< 1 2) (not false) (is-eq u1 (to-uint 1)))"
+            )),
+            Some(Value::Bool(true))
+        );
+        // short-circuits past a non-constant argument, like evaluation does
+        assert_eq!(
+            const_fold(&parse_expr("(or true (var-get x))")),
+            Some(Value::Bool(true))
+        );
+
+        // state, variables and non-arithmetic functions aren't folded
+        assert_eq!(const_fold(&parse_expr("(var-get x)")), None);
+        assert_eq!(const_fold(&parse_expr("(+ u1 (var-get x))")), None);
+        assert_eq!(const_fold(&parse_expr("(+ x 1)")), None);
+        assert_eq!(const_fold(&parse_expr("(foo 1)")), None);
+        assert_eq!(const_fold(&parse_expr("(print 1)")), None);
+    }
+
+    #[test]
+    fn test_try_const_fold_errors() {
+        let overflow = parse_expr("(* u340282366920938463463374607431768211455 u2)");
+        assert_eq!(const_fold(&overflow), None);
+        match try_const_fold(&overflow) {
+            Some(Err(Error::Runtime(RuntimeErrorType::ArithmeticOverflow, _))) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+        match try_const_fold(&parse_expr("(/ 1 (- 2 2))")) {
+            Some(Err(Error::Runtime(RuntimeErrorType::DivisionByZero, _))) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+        // not a constant expression, even though it would fail
+        assert!(try_const_fold(&parse_expr("(/ (var-get x) 0)")).is_none());
+    }
+}
@@ -32,16 +32,21 @@ use std::iter::FromIterator;
 #[cfg(test)]
 mod tests;
 
+/// Maximum number of top-level `define-*` forms a contract may contain from Stacks 2.1
+pub const MAX_TOP_LEVEL_DEFINITIONS: usize = 2048;
+
 pub struct DefinitionSorter {
     graph: Graph,
     top_level_expressions_map: HashMap<ClarityName, TopLevelExpressionIndex>,
+    max_definitions: Option<usize>,
 }
 
 impl<'a> DefinitionSorter {
-    fn new() -> Self {
+    fn new(max_definitions: Option<usize>) -> Self {
         Self {
             top_level_expressions_map: HashMap::new(),
             graph: Graph::new(),
+            max_definitions,
         }
     }
 
@@ -49,7 +54,19 @@ impl<'a> DefinitionSorter {
         contract_ast: &mut ContractAST,
         accounting: &mut T,
     ) -> ParseResult<()> {
-        let mut pass = DefinitionSorter::new();
+        let mut pass = DefinitionSorter::new(None);
+        pass.run(contract_ast, accounting)?;
+        Ok(())
+    }
+
+    /// Like `run_pass`, but fails with `TooManyDefinitions` once the contract has more
+    /// than `max_definitions` top-level definitions.
+    pub fn run_pass_with_limit<T: CostTracker>(
+        contract_ast: &mut ContractAST,
+        accounting: &mut T,
+        max_definitions: usize,
+    ) -> ParseResult<()> {
+        let mut pass = DefinitionSorter::new(Some(max_definitions));
         pass.run(contract_ast, accounting)?;
         Ok(())
     }
@@ -60,6 +77,18 @@ impl<'a> DefinitionSorter {
         accounting: &mut T,
     ) -> ParseResult<()> {
         let exprs = contract_ast.pre_expressions[..].to_vec();
+        if let Some(max_definitions) = self.max_definitions {
+            let definitions_count = exprs
+                .iter()
+                .filter(|expr| self.find_expression_definition(expr).is_some())
+                .count();
+            if definitions_count > max_definitions {
+                return Err(
+                    ParseErrors::TooManyDefinitions(definitions_count, max_definitions).into(),
+                );
+            }
+        }
+
         for (expr_index, expr) in exprs.iter().enumerate() {
             self.graph.add_node(expr_index);
 
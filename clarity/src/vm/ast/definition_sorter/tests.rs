@@ -309,3 +309,33 @@ fn should_not_conflict_with_atoms_from_trait_definitions() {
 
     run_scoped_parsing_helper(contract).unwrap();
 }
+
+#[test]
+fn should_not_limit_definitions_by_default() {
+    let contract = (0..4096)
+        .map(|i| format!("(define-constant c{} {})", i, i))
+        .collect::<Vec<_>>()
+        .join("\n");
+    run_scoped_parsing_helper(&contract).unwrap();
+}
+
+#[test]
+fn should_apply_configured_definitions_limit() {
+    let contract = "(define-constant a 1) (define-constant b 2) (define-constant c 3)";
+    let pre_expressions = parser::parse(contract).unwrap();
+    let mut contract_ast =
+        ContractAST::new(QualifiedContractIdentifier::transient(), pre_expressions);
+    ExpressionIdentifier::run_pre_expression_pass(&mut contract_ast).unwrap();
+
+    let err = DefinitionSorter::run_pass_with_limit(&mut contract_ast, &mut (), 2).unwrap_err();
+    assert_eq!(err.err, ParseErrors::TooManyDefinitions(3, 2));
+    DefinitionSorter::run_pass_with_limit(&mut contract_ast, &mut (), 3).unwrap();
+
+    // non-definition top-level expressions do not count towards the limit
+    let contract = "(define-constant a 1) (define-constant b 2) (print a) (print b)";
+    let pre_expressions = parser::parse(contract).unwrap();
+    let mut contract_ast =
+        ContractAST::new(QualifiedContractIdentifier::transient(), pre_expressions);
+    ExpressionIdentifier::run_pre_expression_pass(&mut contract_ast).unwrap();
+    DefinitionSorter::run_pass_with_limit(&mut contract_ast, &mut (), 2).unwrap();
+}
@@ -16,7 +16,7 @@
 
 use crate::vm::costs::{CostErrors, ExecutionCost};
 use crate::vm::diagnostic::{DiagnosableError, Diagnostic};
-use crate::vm::representations::PreSymbolicExpression;
+use crate::vm::representations::{PreSymbolicExpression, MAX_STRING_LEN};
 use crate::vm::types::{TupleTypeSignature, TypeSignature};
 use crate::vm::MAX_CALL_STACK_DEPTH;
 use std::error;
@@ -36,6 +36,7 @@ pub enum ParseErrors {
     SeparatorExpectedAfterColon(String),
     ProgramTooLarge,
     IllegalVariableName(String),
+    NameTooLong(usize),
     IllegalContractName(String),
     UnknownQuotedValue(String),
     FailedParsingIntValue(String),
@@ -49,10 +50,12 @@ pub enum ParseErrors {
     ClosingTupleLiteralUnexpected,
     ClosingTupleLiteralExpected,
     CircularReference(Vec<String>),
+    TooManyDefinitions(usize, usize),
     TupleColonExpected(usize),
     TupleCommaExpected(usize),
     TupleItemExpected(usize),
     NameAlreadyUsed(String),
+    ReservedName(String),
     TraitReferenceNotAllowed,
     ImportTraitBadSignature,
     DefineTraitBadSignature,
@@ -172,6 +175,10 @@ impl DiagnosableError for ParseErrors {
             ParseErrors::IllegalVariableName(var_name) => {
                 format!("Illegal variable name: '{}'", var_name)
             }
+            ParseErrors::NameTooLong(len) => format!(
+                "Name is {} characters long, but the maximum is {}",
+                len, MAX_STRING_LEN
+            ),
             ParseErrors::UnknownQuotedValue(value) => format!("Unknown 'quoted value '{}'", value),
             ParseErrors::FailedParsingIntValue(value) => {
                 format!("Failed to parse int literal '{}'", value)
@@ -215,6 +222,16 @@ impl DiagnosableError for ParseErrors {
                 "detected interdependent functions ({})",
                 function_names.join(", ")
             ),
+            ParseErrors::TooManyDefinitions(count, limit) => format!(
+                "contract has too many top-level definitions ({}, limit is {})",
+                count, limit
+            ),
+            ParseErrors::ReservedName(name) => {
+                format!(
+                    "'{}' is a reserved keyword and cannot be used as a name",
+                    name
+                )
+            }
             ParseErrors::NameAlreadyUsed(name) => {
                 format!("defining '{}' conflicts with previous value", name)
             }
@@ -14,29 +14,35 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+pub mod const_folder;
 pub mod definition_sorter;
 pub mod expression_identifier;
 pub mod parser;
+pub mod reserved_name_checker;
+pub mod trait_reference_checker;
 pub mod traits_resolver;
 
 pub mod errors;
 pub mod stack_depth_checker;
 pub mod sugar_expander;
 pub mod types;
+use crate::types::StacksEpochId;
 use crate::vm::costs::{cost_functions, runtime_cost, CostTracker, LimitedCostTracker};
 use crate::vm::errors::{Error, RuntimeErrorType};
 
 use crate::vm::representations::SymbolicExpression;
 use crate::vm::types::QualifiedContractIdentifier;
 
-use self::definition_sorter::DefinitionSorter;
-use self::errors::ParseResult;
+use self::definition_sorter::{DefinitionSorter, MAX_TOP_LEVEL_DEFINITIONS};
+use self::errors::{ParseError, ParseResult};
 use self::expression_identifier::ExpressionIdentifier;
+use self::reserved_name_checker::ReservedNameChecker;
 use self::stack_depth_checker::StackDepthChecker;
 use self::sugar_expander::SugarExpander;
+use self::trait_reference_checker::TraitReferenceChecker;
 use self::traits_resolver::TraitsResolver;
 use self::types::BuildASTPass;
-pub use self::types::ContractAST;
+pub use self::types::{ContractAST, ContractASTPass};
 use crate::vm::costs::cost_functions::ClarityCostFunction;
 
 /// Legacy function
@@ -48,35 +54,166 @@ pub fn parse(
     Ok(ast.expressions)
 }
 
+/// The stages of building a contract's AST, in the order `build_ast` runs them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildAstStage {
+    /// Charging the parse cost and parsing the source into pre-expressions
+    Parse,
+    StackDepthCheck,
+    PreExpressionIdentification,
+    /// Rejecting reserved trait and asset names, which only runs from Stacks 2.1
+    ReservedNameCheck,
+    DefinitionSorting,
+    TraitResolution,
+    SugarExpansion,
+    TraitReferenceCheck,
+    /// Running the extra pass at this index of the list given to `build_ast_with_passes`
+    ExtraPass(usize),
+    ExpressionIdentification,
+}
+
+/// Legacy function: build a contract's AST with the rules of Stacks 2.05
 pub fn build_ast<T: CostTracker>(
     contract_identifier: &QualifiedContractIdentifier,
     source_code: &str,
     cost_track: &mut T,
 ) -> ParseResult<ContractAST> {
+    build_ast_in_epoch(
+        contract_identifier,
+        source_code,
+        cost_track,
+        StacksEpochId::Epoch2_05,
+    )
+}
+
+pub fn build_ast_in_epoch<T: CostTracker>(
+    contract_identifier: &QualifiedContractIdentifier,
+    source_code: &str,
+    cost_track: &mut T,
+    epoch: StacksEpochId,
+) -> ParseResult<ContractAST> {
+    build_ast_with_stage(contract_identifier, source_code, cost_track, epoch).map_err(|(_, e)| e)
+}
+
+/// Like `build_ast_in_epoch`, but on failure also reports the stage that failed
+pub fn build_ast_with_stage<T: CostTracker>(
+    contract_identifier: &QualifiedContractIdentifier,
+    source_code: &str,
+    cost_track: &mut T,
+    epoch: StacksEpochId,
+) -> Result<ContractAST, (BuildAstStage, ParseError)> {
+    build_ast_with_passes(contract_identifier, source_code, cost_track, vec![], epoch)
+}
+
+/// Like `build_ast_with_stage`, but runs `extra_passes`, in order, once the expressions
+///  have been expanded and checked.  Expressions are identified after the extra passes run,
+///  so they may add or rewrite expressions.
+pub fn build_ast_with_passes<T: CostTracker>(
+    contract_identifier: &QualifiedContractIdentifier,
+    source_code: &str,
+    cost_track: &mut T,
+    extra_passes: Vec<Box<dyn ContractASTPass>>,
+    epoch: StacksEpochId,
+) -> Result<ContractAST, (BuildAstStage, ParseError)> {
+    let at = |stage| move |e: ParseError| (stage, e);
+
+    let mut contract_ast =
+        build_pre_ast_with_stage(contract_identifier, source_code, cost_track, epoch)?;
+    SugarExpander::run_pass(&mut contract_ast).map_err(at(BuildAstStage::SugarExpansion))?;
+    if epoch >= StacksEpochId::Epoch21 {
+        TraitReferenceChecker::run_pass(&mut contract_ast)
+            .map_err(at(BuildAstStage::TraitReferenceCheck))?;
+    }
+    for (index, pass) in extra_passes.iter().enumerate() {
+        pass.run(&mut contract_ast)
+            .map_err(at(BuildAstStage::ExtraPass(index)))?;
+    }
+    ExpressionIdentifier::run_expression_pass(&mut contract_ast)
+        .map_err(at(BuildAstStage::ExpressionIdentification))?;
+    Ok(contract_ast)
+}
+
+/// Build a contract's AST up to, but not including, sugar expansion.  The returned AST's
+///  `pre_expressions` are sorted, identified and have their traits resolved, but sugared
+///  identifiers and trait references are left as written, and `expressions` is empty.
+pub fn build_pre_ast(
+    contract_identifier: &QualifiedContractIdentifier,
+    source_code: &str,
+) -> ParseResult<ContractAST> {
+    build_pre_ast_with_stage(
+        contract_identifier,
+        source_code,
+        &mut (),
+        StacksEpochId::Epoch2_05,
+    )
+    .map_err(|(_, e)| e)
+}
+
+/// Run the stages of `build_ast` that precede sugar expansion
+fn build_pre_ast_with_stage<T: CostTracker>(
+    contract_identifier: &QualifiedContractIdentifier,
+    source_code: &str,
+    cost_track: &mut T,
+    epoch: StacksEpochId,
+) -> Result<ContractAST, (BuildAstStage, ParseError)> {
+    let at = |stage| move |e: ParseError| (stage, e);
+
     runtime_cost(
         ClarityCostFunction::AstParse,
         cost_track,
         source_code.len() as u64,
-    )?;
-    let pre_expressions = parser::parse(source_code)?;
+    )
+    .map_err(|e| (BuildAstStage::Parse, ParseError::from(e)))?;
+    let pre_expressions =
+        parser::parse_in_epoch(source_code, epoch).map_err(at(BuildAstStage::Parse))?;
     let mut contract_ast = ContractAST::new(contract_identifier.clone(), pre_expressions);
-    StackDepthChecker::run_pass(&mut contract_ast)?;
-    ExpressionIdentifier::run_pre_expression_pass(&mut contract_ast)?;
-    DefinitionSorter::run_pass(&mut contract_ast, cost_track)?;
-    TraitsResolver::run_pass(&mut contract_ast)?;
-    SugarExpander::run_pass(&mut contract_ast)?;
-    ExpressionIdentifier::run_expression_pass(&mut contract_ast)?;
+    StackDepthChecker::run_pass(&mut contract_ast).map_err(at(BuildAstStage::StackDepthCheck))?;
+    ExpressionIdentifier::run_pre_expression_pass(&mut contract_ast)
+        .map_err(at(BuildAstStage::PreExpressionIdentification))?;
+    if epoch >= StacksEpochId::Epoch21 {
+        ReservedNameChecker::run_pass(&mut contract_ast)
+            .map_err(at(BuildAstStage::ReservedNameCheck))?;
+    }
+    if epoch >= StacksEpochId::Epoch21 {
+        DefinitionSorter::run_pass_with_limit(
+            &mut contract_ast,
+            cost_track,
+            MAX_TOP_LEVEL_DEFINITIONS,
+        )
+    } else {
+        DefinitionSorter::run_pass(&mut contract_ast, cost_track)
+    }
+    .map_err(at(BuildAstStage::DefinitionSorting))?;
+    TraitsResolver::run_pass(&mut contract_ast).map_err(at(BuildAstStage::TraitResolution))?;
     Ok(contract_ast)
 }
 
 #[cfg(test)]
 mod test {
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-    use crate::vm::ast::build_ast;
+    use crate::types::StacksEpochId;
+    use crate::vm::ast::definition_sorter::MAX_TOP_LEVEL_DEFINITIONS;
+    use crate::vm::ast::errors::ParseErrors;
+    use crate::vm::ast::errors::{ParseError, ParseResult};
+    use crate::vm::ast::expression_identifier::ExpressionIdentifier;
+    use crate::vm::ast::sugar_expander::SugarExpander;
+    use crate::vm::ast::trait_reference_checker::TraitReferenceChecker;
+    use crate::vm::ast::types::BuildASTPass;
+    use crate::vm::ast::types::{StorageFootprint, TraitConformanceError, TOP_LEVEL_PARENT_ID};
+    use crate::vm::ast::{
+        build_ast, build_ast_in_epoch, build_ast_with_passes, build_ast_with_stage, build_pre_ast,
+        BuildAstStage, ContractAST, ContractASTPass,
+    };
+    use crate::vm::callables::DefineType;
     use crate::vm::costs::LimitedCostTracker;
-    use crate::vm::representations::depth_traverse;
-    use crate::vm::types::QualifiedContractIdentifier;
+    use crate::vm::diagnostic::Level;
+    use crate::vm::representations::{
+        depth_traverse, PreSymbolicExpression, PreSymbolicExpressionType, SymbolicExpression,
+    };
+    use crate::vm::types::signatures::FunctionSignature;
+    use crate::vm::types::{QualifiedContractIdentifier, TypeSignature};
+    use crate::vm::ClarityName;
 
     #[test]
     fn test_expression_identification_tuples() {
@@ -104,4 +241,529 @@ mod test {
             .unwrap();
         }
     }
+
+    fn make_trait_methods(balance_arg: TypeSignature) -> BTreeMap<ClarityName, FunctionSignature> {
+        let response =
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::UIntType).unwrap();
+        let mut methods = BTreeMap::new();
+        methods.insert(
+            "get-balance".into(),
+            FunctionSignature {
+                args: vec![balance_arg],
+                returns: response.clone(),
+            },
+        );
+        methods.insert(
+            "get-supply".into(),
+            FunctionSignature {
+                args: vec![],
+                returns: response,
+            },
+        );
+        methods
+    }
+
+    #[test]
+    fn test_check_implements_trait() {
+        let contract = "(define-public (get-balance (who principal)) (ok u1))
+             (define-read-only (get-supply) (ok u100))
+             (define-private (helper (x int)) x)";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+
+        let conforming = make_trait_methods(TypeSignature::PrincipalType);
+        assert_eq!(ast.check_implements_trait(&conforming), vec![]);
+
+        let mut missing = make_trait_methods(TypeSignature::PrincipalType);
+        missing.insert(
+            "helper".into(),
+            FunctionSignature {
+                args: vec![TypeSignature::IntType],
+                returns: TypeSignature::IntType,
+            },
+        );
+        assert_eq!(
+            ast.check_implements_trait(&missing),
+            vec![TraitConformanceError::MissingMethod("helper".into())]
+        );
+
+        let wrong_arg = make_trait_methods(TypeSignature::UIntType);
+        let errors = ast.check_implements_trait(&wrong_arg);
+        assert_eq!(errors.len(), 1);
+        match &errors[0] {
+            TraitConformanceError::SignatureMismatch(name, span) => {
+                assert_eq!(name.as_str(), "get-balance");
+                assert_eq!(span, &ast.expressions[0].span);
+            }
+            e => panic!("Unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_strip_spans() {
+        let contract = "(define-map scores principal { points: int, level: uint })
+(define-public (score (points int))
+  (begin (map-set scores tx-sender { points: points, level: u1 })
+         (ok points)))";
+        let reformatted = "  (define-map scores principal {points: int,   level: uint})
+
+(define-public (score (points int)) (begin
+    (map-set scores tx-sender {points: points, level: u1})
+    (ok points)))";
+
+        let contract_id = QualifiedContractIdentifier::transient();
+        let mut cost_track = LimitedCostTracker::new_free();
+        let mut ast = build_ast(&contract_id, contract, &mut cost_track).unwrap();
+        let mut reformatted_ast = build_ast(&contract_id, reformatted, &mut cost_track).unwrap();
+        assert!(ast != reformatted_ast);
+
+        let stripped = ast.clone_without_spans();
+        assert_eq!(stripped, reformatted_ast.clone_without_spans());
+
+        ast.strip_spans();
+        reformatted_ast.strip_spans();
+        assert_eq!(ast, reformatted_ast);
+        assert_eq!(ast, stripped);
+    }
+
+    fn assert_source_round_trips(contract: &str) -> String {
+        let contract_id = QualifiedContractIdentifier::transient();
+        let ast = build_ast(&contract_id, contract, &mut ()).unwrap();
+        let source = ast.to_source();
+        let reparsed = build_ast(&contract_id, &source, &mut ()).unwrap();
+        assert_eq!(ast.clone_without_spans(), reparsed.clone_without_spans());
+        // rendering is canonical
+        assert_eq!(reparsed.to_source(), source);
+        source
+    }
+
+    #[test]
+    fn test_to_source_nested_let() {
+        let contract = r#"(define-read-only (f (x int))
+           (let ((a (+ x -1))
+                 (b { first: a, second: "two" }))
+             (if (> a 0) (ok (get first b)) (err u1))))
+         (define-constant very-long-constant-name-for-testing (list u1 u2 u3 u4 u5 u6 u7 u8 u9 u10))"#;
+        let source = assert_source_round_trips(contract);
+        assert_eq!(
+            source,
+            r#"(define-read-only (f (x int))
+  (let ((a (+ x -1)) (b (tuple (first a) (second "two"))))
+    (if (> a 0) (ok (get first b)) (err u1))))
+(define-constant very-long-constant-name-for-testing
+  (list u1 u2 u3 u4 u5 u6 u7 u8 u9 u10))
+"#
+        );
+    }
+
+    #[test]
+    fn test_to_source_token_contract() {
+        let contract = r#"
+            (define-trait token-trait ((transfer? (uint principal) (response bool uint))))
+            (impl-trait .token-trait-contract.token-trait)
+            (define-fungible-token stackaroos u1000000)
+            (define-non-fungible-token stickers (buff 32))
+            (define-constant owner 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM)
+            (define-constant greeting u"caf\u{e9} \"quoted\"\n")
+            (define-data-var memo (string-ascii 20) "tab\there\\")
+            (define-public (transfer (amount uint) (to principal) (t <token-trait>))
+              (begin
+                (try! (ft-transfer? stackaroos amount tx-sender to))
+                (try! (nft-mint? stickers 0x0102030405 to))
+                (contract-call? .other-contract ping 'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM.foo)))"#;
+        let source = assert_source_round_trips(contract);
+        assert!(source.contains(r#"(define-constant greeting u"caf\u{e9} \"quoted\"\n")"#));
+        assert!(source.contains(r#"(define-data-var memo (string-ascii 20) "tab\there\\")"#));
+        assert!(source.contains("(nft-mint? stickers 0x0102030405 to)"));
+        assert!(source.contains(
+            "(impl-trait 'S1G2081040G2081040G2081040G208105NK8PE5.token-trait-contract.token-trait)"
+        ));
+        assert!(source.contains(
+            "    (contract-call? 'S1G2081040G2081040G2081040G208105NK8PE5.other-contract
+      ping
+      'ST1PQHQKV0RJXZFY1DGX8MNSNYVE3VGZJSRTPGZGM.foo)))"
+        ));
+    }
+
+    #[test]
+    fn test_storage_footprint() {
+        let contract = "(define-map balances principal uint)
+             (define-data-var counter int 0)
+             (define-data-var paused bool false)
+             (define-fungible-token gold u100)
+             (define-fungible-token silver)
+             (define-non-fungible-token badge uint)
+             (define-constant owner tx-sender)
+             (define-read-only (get-counter) (var-get counter))";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+        let footprint = ast.storage_footprint();
+
+        let names = |names: &[&str]| -> BTreeSet<ClarityName> {
+            names.iter().map(|name| (*name).into()).collect()
+        };
+        assert_eq!(footprint.maps, names(&["balances"]));
+        assert_eq!(footprint.data_vars, names(&["counter", "paused"]));
+        assert_eq!(footprint.fungible_tokens, names(&["gold", "silver"]));
+        assert_eq!(footprint.non_fungible_tokens, names(&["badge"]));
+
+        let ast = build_ast(
+            &QualifiedContractIdentifier::transient(),
+            "(define-private (f) u1)",
+            &mut (),
+        )
+        .unwrap();
+        assert_eq!(ast.storage_footprint(), StorageFootprint::default());
+    }
+
+    #[test]
+    fn test_unreachable_statements() {
+        let contract = "(define-public (f)
+               (begin
+                 (print u1)
+                 (asserts! false (err u1))
+                 (print u2)
+                 (ok true)))
+             (define-private (g) (begin (asserts! false (err u1)) (print u3)))
+             (define-read-only (h) (begin (ok u1) (print u4) (err u1)))
+             (define-read-only (i (x (optional uint)))
+               (begin (unwrap! x (err u1)) (asserts! true (err u2)) (ok u1)))
+             (define-public (j)
+               (begin (unwrap-err! (ok u1) (err u1)) (ok u2)))
+             (define-public (k)
+               (begin (try! (err u1)) (unwrap-panic none) (ok u2)))";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+
+        let unreachable: Vec<_> = ast
+            .unreachable_statements()
+            .into_iter()
+            .map(|span| {
+                (
+                    span.start_line,
+                    span.start_column,
+                    span.end_line,
+                    span.end_column,
+                )
+            })
+            .collect();
+        // `begin` evaluates every statement, so a bare `(ok ...)` doesn't end `h`, `i` only
+        //  exits early if `x` is none, and private functions like `g` aren't scanned
+        assert_eq!(
+            unreachable,
+            vec![
+                (5, 18, 5, 27),
+                (6, 18, 6, 26),
+                (12, 54, 12, 60),
+                (14, 39, 14, 57),
+                (14, 59, 14, 65),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expression_parents() {
+        let contract = "(define-constant a u1)
+             (define-private (f (x int))
+               (let ((y (+ x 1)))
+                 (* y 2)))";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+
+        // find the `+` atom, nested as (define-private _ (let ((y (+ ...))) _))
+        let define = &ast.expressions[1];
+        let let_expr = &define.match_list().unwrap()[2];
+        let bindings = &let_expr.match_list().unwrap()[1];
+        let binding = &bindings.match_list().unwrap()[0];
+        let sum = &binding.match_list().unwrap()[1];
+        let plus = &sum.match_list().unwrap()[0];
+        assert_eq!(plus.match_atom().unwrap().as_str(), "+");
+
+        let parents = ast.build_parent_map();
+        let mut chain = vec![];
+        let mut id = plus.id;
+        while id != TOP_LEVEL_PARENT_ID {
+            id = parents[&id];
+            chain.push(id);
+        }
+        assert_eq!(
+            chain,
+            vec![
+                sum.id,
+                binding.id,
+                bindings.id,
+                let_expr.id,
+                define.id,
+                TOP_LEVEL_PARENT_ID
+            ]
+        );
+        assert_eq!(
+            parents.get(&ast.expressions[0].id),
+            Some(&TOP_LEVEL_PARENT_ID)
+        );
+        assert_eq!(parents.get(&10_000), None);
+    }
+
+    #[test]
+    fn test_shadowing_warnings() {
+        let contract = "(define-private (f (amount uint))
+               (let ((amount u2)) amount))";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+        let warnings = ast.shadowing_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].level, Level::Warning);
+        assert_eq!(
+            warnings[0]
+                .spans
+                .iter()
+                .map(|span| (span.start_line, span.start_column, span.end_column))
+                .collect::<Vec<_>>(),
+            vec![(2, 23, 28), (1, 21, 26)]
+        );
+
+        // an inner let shadowing an outer one
+        let contract = "(define-read-only (g)
+               (let ((x 1)) (let ((y x) (x 2)) (+ x y))))";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+        let warnings = ast.shadowing_warnings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            (
+                warnings[0].spans[0].start_column,
+                warnings[0].spans[1].start_column
+            ),
+            (42, 23)
+        );
+
+        let contract = "(define-public (h (a int))
+               (let ((b a)) (let ((c b)) (ok (+ a b c)))))
+             (define-private (i (b int)) b)";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+        assert!(ast.shadowing_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_public_function_signatures() {
+        let contract = "(define-public (transfer (amount uint) (to principal)) (ok true))
+             (define-private (helper (x int)) x)
+             (define-read-only (get-supply) (ok u100))";
+        let ast = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ()).unwrap();
+
+        assert_eq!(
+            ast.public_function_signatures(),
+            vec![
+                ("transfer".into(), 2, DefineType::Public),
+                ("get-supply".into(), 0, DefineType::ReadOnly),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_ast_failure_stage() {
+        let contract_id = QualifiedContractIdentifier::transient();
+
+        let (stage, err) = build_ast_with_stage(
+            &contract_id,
+            "(foo (bar)",
+            &mut (),
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap_err();
+        assert_eq!(stage, BuildAstStage::Parse);
+        assert_eq!(err.err, ParseErrors::ClosingParenthesisExpected);
+
+        let circular = "(define-private (foo) (bar)) (define-private (bar) (foo))";
+        let (stage, err) =
+            build_ast_with_stage(&contract_id, circular, &mut (), StacksEpochId::Epoch2_05)
+                .unwrap_err();
+        assert_eq!(stage, BuildAstStage::DefinitionSorting);
+        assert!(match err.err {
+            ParseErrors::CircularReference(_) => true,
+            _ => false,
+        });
+
+        let unknown_trait = "(define-public (foo (t <undeclared-trait>)) (ok true))";
+        let (stage, err) = build_ast_with_stage(
+            &contract_id,
+            unknown_trait,
+            &mut (),
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap_err();
+        assert_eq!(stage, BuildAstStage::TraitResolution);
+        assert_eq!(
+            err.err,
+            ParseErrors::TraitReferenceUnknown("undeclared-trait".to_string())
+        );
+
+        // the legacy entry point reports the same errors
+        assert_eq!(
+            build_ast(&contract_id, unknown_trait, &mut ())
+                .unwrap_err()
+                .err,
+            ParseErrors::TraitReferenceUnknown("undeclared-trait".to_string())
+        );
+        assert!(build_ast_with_stage(
+            &contract_id,
+            "(foo (bar))",
+            &mut (),
+            StacksEpochId::Epoch2_05
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_build_ast_in_epoch() {
+        let contract_id = QualifiedContractIdentifier::transient();
+        let contract = "\u{feff}(define-constant a 1)\0";
+
+        let (stage, _) =
+            build_ast_with_stage(&contract_id, contract, &mut (), StacksEpochId::Epoch2_05)
+                .unwrap_err();
+        assert_eq!(stage, BuildAstStage::Parse);
+        assert!(build_ast(&contract_id, contract, &mut ()).is_err());
+
+        let ast =
+            build_ast_in_epoch(&contract_id, contract, &mut (), StacksEpochId::Epoch21).unwrap();
+        assert_eq!(ast.expressions.len(), 1);
+    }
+
+    #[test]
+    fn test_top_level_definitions_limit() {
+        let contract_id = QualifiedContractIdentifier::transient();
+        let make_contract = |count: usize| {
+            (0..count)
+                .map(|i| format!("(define-constant c{} {})", i, i))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let at_limit = make_contract(MAX_TOP_LEVEL_DEFINITIONS);
+        let over_limit = make_contract(MAX_TOP_LEVEL_DEFINITIONS + 1);
+
+        build_ast_with_stage(&contract_id, &at_limit, &mut (), StacksEpochId::Epoch21).unwrap();
+        let (stage, err) =
+            build_ast_with_stage(&contract_id, &over_limit, &mut (), StacksEpochId::Epoch21)
+                .unwrap_err();
+        assert_eq!(stage, BuildAstStage::DefinitionSorting);
+        assert_eq!(
+            err.err,
+            ParseErrors::TooManyDefinitions(
+                MAX_TOP_LEVEL_DEFINITIONS + 1,
+                MAX_TOP_LEVEL_DEFINITIONS
+            )
+        );
+
+        // before 2.1, there is no limit
+        build_ast_with_stage(&contract_id, &over_limit, &mut (), StacksEpochId::Epoch2_05).unwrap();
+    }
+
+    /// Appends a `(tag <name>)` expression to the contract
+    struct TagPass(&'static str);
+
+    impl ContractASTPass for TagPass {
+        fn run(&self, contract_ast: &mut ContractAST) -> ParseResult<()> {
+            let tag = vec![
+                SymbolicExpression::atom(ClarityName::try_from("tag").unwrap()),
+                SymbolicExpression::atom(ClarityName::try_from(self.0).unwrap()),
+            ];
+            contract_ast
+                .expressions
+                .push(SymbolicExpression::list(tag.into_boxed_slice()));
+            Ok(())
+        }
+    }
+
+    struct RejectPass;
+
+    impl ContractASTPass for RejectPass {
+        fn run(&self, _contract_ast: &mut ContractAST) -> ParseResult<()> {
+            Err(ParseError::new(ParseErrors::ProgramTooLarge))
+        }
+    }
+
+    #[test]
+    fn test_build_ast_with_passes() {
+        let contract_id = QualifiedContractIdentifier::transient();
+        let contract = "(define-constant a 1)";
+
+        let ast = build_ast_with_passes(
+            &contract_id,
+            contract,
+            &mut (),
+            vec![Box::new(TagPass("first")), Box::new(TagPass("second"))],
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap();
+        assert_eq!(ast.expressions.len(), 3);
+        let tags: Vec<_> = ast.expressions[1..]
+            .iter()
+            .map(|expr| {
+                expr.match_list().unwrap()[1]
+                    .match_atom()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(tags, vec!["first".to_string(), "second".to_string()]);
+        // the added expressions are identified along with the rest
+        assert!(ast.expressions.iter().all(|expr| expr.id != 0));
+
+        let (stage, err) = build_ast_with_passes(
+            &contract_id,
+            contract,
+            &mut (),
+            vec![Box::new(TagPass("first")), Box::new(RejectPass)],
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap_err();
+        assert_eq!(stage, BuildAstStage::ExtraPass(1));
+        assert_eq!(err.err, ParseErrors::ProgramTooLarge);
+
+        assert_eq!(
+            build_ast_with_passes(
+                &contract_id,
+                contract,
+                &mut (),
+                vec![],
+                StacksEpochId::Epoch2_05
+            )
+            .unwrap(),
+            build_ast(&contract_id, contract, &mut ()).unwrap()
+        );
+    }
+
+    fn count_sugared(pre_exprs: &[PreSymbolicExpression]) -> (usize, usize) {
+        let mut counts = (0, 0);
+        for pre_expr in pre_exprs.iter() {
+            match pre_expr.pre_expr {
+                PreSymbolicExpressionType::SugaredContractIdentifier(_) => counts.0 += 1,
+                PreSymbolicExpressionType::TraitReference(_) => counts.1 += 1,
+                PreSymbolicExpressionType::List(ref inner)
+                | PreSymbolicExpressionType::Tuple(ref inner) => {
+                    let inner_counts = count_sugared(inner);
+                    counts.0 += inner_counts.0;
+                    counts.1 += inner_counts.1;
+                }
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn test_build_pre_ast() {
+        let contract_id = QualifiedContractIdentifier::local("pre-ast").unwrap();
+        let contract = "(define-trait token ((transfer (uint principal) (response bool uint))))
+            (impl-trait .token-trait.token)
+            (define-public (pay (t <token>) (amount uint))
+              (contract-call? t transfer amount .recipient))";
+
+        let mut pre_ast = build_pre_ast(&contract_id, contract).unwrap();
+        assert!(pre_ast.expressions.is_empty());
+        assert_eq!(pre_ast.pre_expressions.len(), 3);
+        // `.token-trait.token` is a sugared field identifier, not a sugared contract identifier
+        assert_eq!(count_sugared(&pre_ast.pre_expressions), (1, 1));
+        assert_eq!(pre_ast.implemented_traits.len(), 1);
+
+        SugarExpander::run_pass(&mut pre_ast).unwrap();
+        TraitReferenceChecker::run_pass(&mut pre_ast).unwrap();
+        ExpressionIdentifier::run_expression_pass(&mut pre_ast).unwrap();
+        assert_eq!(pre_ast, build_ast(&contract_id, contract, &mut ()).unwrap());
+    }
 }
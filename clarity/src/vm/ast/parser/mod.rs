@@ -14,10 +14,13 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::types::StacksEpochId;
 use crate::vm::ast::errors::{ParseError, ParseErrors, ParseResult};
+use crate::vm::diagnostic::Diagnostic;
 use crate::vm::errors::{InterpreterResult as Result, RuntimeErrorType};
 use crate::vm::representations::{
-    ClarityName, ContractName, PreSymbolicExpression, PreSymbolicExpressionType, MAX_STRING_LEN,
+    ByteSpan, ClarityName, ContractName, PreSymbolicExpression, PreSymbolicExpressionType, Span,
+    MAX_STRING_LEN,
 };
 use crate::vm::types::{PrincipalData, QualifiedContractIdentifier, TraitIdentifier, Value};
 use regex::{Captures, Regex};
@@ -126,20 +129,35 @@ lazy_static! {
         format!(r#"([[:word:]]|[-!?+<>=/*]){{1,{}}}"#, MAX_STRING_LEN);
 }
 
-pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
+/// Legacy function: lex `input` with the rules of Stacks 2.05
+pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32, ByteSpan)>> {
+    lex_in_epoch(input, StacksEpochId::Epoch2_05)
+}
+
+pub fn lex_in_epoch(
+    input: &str,
+    epoch: StacksEpochId,
+) -> ParseResult<Vec<(LexItem, u32, u32, ByteSpan)>> {
     // Aaron: I'd like these to be static, but that'd require using
     //    lazy_static (or just hand implementing that), and I'm not convinced
     //    it's worth either (1) an extern macro, or (2) the complexity of hand implementing.
 
-    let lex_matchers: &[LexMatcher] = &[
-        LexMatcher::new(
+    // from Stacks 2.1, a string literal can continue on the next line after an escaped line break
+    let (utf8_string_regex, ascii_string_regex) = if epoch >= StacksEpochId::Epoch21 {
+        (
+            r##"u"(?P<value>((\\")|(\\\n)|([[ -~]&&[^"]]))*)""##,
+            r##""(?P<value>((\\")|(\\\n)|([[ -~]&&[^"]]))*)""##,
+        )
+    } else {
+        (
             r##"u"(?P<value>((\\")|([[ -~]&&[^"]]))*)""##,
-            TokenType::StringUTF8Literal,
-        ),
-        LexMatcher::new(
             r##""(?P<value>((\\")|([[ -~]&&[^"]]))*)""##,
-            TokenType::StringASCIILiteral,
-        ),
+        )
+    };
+
+    let lex_matchers: &[LexMatcher] = &[
+        LexMatcher::new(utf8_string_regex, TokenType::StringUTF8Literal),
+        LexMatcher::new(ascii_string_regex, TokenType::StringASCIILiteral),
         LexMatcher::new(";;[ -~]*", TokenType::Whitespace), // ;; comments.
         LexMatcher::new("[\n]+", TokenType::Whitespace),
         LexMatcher::new("[ \t]+", TokenType::Whitespace),
@@ -182,8 +200,10 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
             &format!("'(?P<value>{})", *STANDARD_PRINCIPAL_REGEX),
             TokenType::PrincipalLiteral,
         ),
+        // names are matched regardless of length, so that an overlong name is reported as such
+        // (rather than as a missing separator after its first MAX_STRING_LEN characters)
         LexMatcher::new(
-            &format!("(?P<value>{})", *CLARITY_NAME_REGEX),
+            r#"(?P<value>([[:word:]]|[-!?+<>=/*])+)"#,
             TokenType::Variable,
         ),
     ];
@@ -199,7 +219,21 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
     let mut column_pos: u32 = 1;
     let mut did_match = true;
     while did_match && munch_index < input.len() {
-        if let Some(next_line_ix) = next_line_break {
+        if epoch >= StacksEpochId::Epoch21 {
+            // a single token (e.g., a run of blank lines, or a string literal with an escaped
+            // line break) can span several lines, so catch up on every line break it consumed.
+            while let Some(next_line_ix) = next_line_break {
+                if munch_index <= next_line_ix {
+                    break;
+                }
+                next_line_break = line_indices.pop();
+                // the source is all ASCII, so byte offsets are columns
+                column_pos = (munch_index - next_line_ix) as u32;
+                current_line = current_line
+                    .checked_add(1)
+                    .ok_or(ParseError::new(ParseErrors::ProgramTooLarge))?;
+            }
+        } else if let Some(next_line_ix) = next_line_break {
             if munch_index > next_line_ix {
                 next_line_break = line_indices.pop();
                 column_pos = 1;
@@ -215,8 +249,30 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
             if let Some(captures) = matcher.matcher.captures(current_slice) {
                 let whole_match = captures.get(0).unwrap();
                 assert_eq!(whole_match.start(), 0);
+                let token_start = munch_index;
                 munch_index += whole_match.end();
 
+                // a missing separator is reported across both tokens it should have separated:
+                //  from the start of the previous token to the end of this one.
+                let separator_error =
+                    |err: ParseErrors, result: &[(LexItem, u32, u32, ByteSpan)]| {
+                        let mut err = ParseError::new(err);
+                        if let Some((_, start_line, start_column, _)) = result.last() {
+                            let (end_line, end_column) = token_end_position(
+                                current_line,
+                                column_pos,
+                                &current_slice[..whole_match.end()],
+                            );
+                            err.diagnostic.spans = vec![Span {
+                                start_line: *start_line,
+                                start_column: *start_column,
+                                end_line,
+                                end_column,
+                            }];
+                        }
+                        err
+                    };
+
                 match context {
                     LexContext::ExpectNothing => Ok(()),
                     LexContext::ExpectClosing => {
@@ -229,9 +285,12 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                             TokenType::Whitespace => Ok(()),
                             TokenType::Comma => Ok(()),
                             TokenType::Colon => Ok(()),
-                            _ => Err(ParseError::new(ParseErrors::SeparatorExpected(
-                                current_slice[..whole_match.end()].to_string(),
-                            ))),
+                            _ => Err(separator_error(
+                                ParseErrors::SeparatorExpected(
+                                    current_slice[..whole_match.end()].to_string(),
+                                ),
+                                &result,
+                            )),
                         }
                     }
                     LexContext::ExpectClosingColon => {
@@ -242,9 +301,12 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                             TokenType::Whitespace => Ok(()),
                             TokenType::Comma => Ok(()),
                             TokenType::Colon => Ok(()),
-                            _ => Err(ParseError::new(ParseErrors::SeparatorExpectedAfterColon(
-                                current_slice[..whole_match.end()].to_string(),
-                            ))),
+                            _ => Err(separator_error(
+                                ParseErrors::SeparatorExpectedAfterColon(
+                                    current_slice[..whole_match.end()].to_string(),
+                                ),
+                                &result,
+                            )),
                         }
                     }
                 }?;
@@ -279,7 +341,14 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                     TokenType::RCurly => Ok(LexItem::RightCurly),
                     TokenType::Variable => {
                         let value = get_value_or_err(current_slice, captures)?;
-                        if value.contains("#") {
+                        if value.len() > MAX_STRING_LEN as usize {
+                            Err(located_error(
+                                ParseErrors::NameTooLong(value.len()),
+                                current_line,
+                                column_pos,
+                                column_pos + value.len() as u32 - 1,
+                            ))
+                        } else if value.contains("#") {
                             Err(ParseError::new(ParseErrors::IllegalVariableName(value)))
                         } else {
                             Ok(LexItem::Variable(value))
@@ -293,7 +362,8 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                                 str_value.clone(),
                             ))),
                         }?;
-                        Ok(LexItem::LiteralValue(str_value.len(), value))
+                        // the literal's span includes its `u` prefix
+                        Ok(LexItem::LiteralValue(str_value.len() + 1, value))
                     }
                     TokenType::IntLiteral => {
                         let str_value = get_value_or_err(current_slice, captures)?;
@@ -387,7 +457,14 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                     TokenType::StringASCIILiteral => {
                         let str_value = get_value_or_err(current_slice, captures)?;
                         let str_value_len = str_value.len();
-                        let unescaped_str = unescape_ascii_chars(str_value, false)?;
+                        // the string's contents start just after the opening `"`
+                        let unescaped_str = unescape_ascii_chars(
+                            str_value,
+                            false,
+                            epoch,
+                            current_line,
+                            column_pos + 1,
+                        )?;
                         let byte_vec = unescaped_str.as_bytes().to_vec();
 
                         let value = match Value::string_ascii_from_bytes(byte_vec) {
@@ -399,7 +476,14 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                     TokenType::StringUTF8Literal => {
                         let str_value = get_value_or_err(current_slice, captures)?;
                         let str_value_len = str_value.len();
-                        let unescaped_str = unescape_ascii_chars(str_value, true)?;
+                        // the string's contents start just after the opening `u"`
+                        let unescaped_str = unescape_ascii_chars(
+                            str_value,
+                            true,
+                            epoch,
+                            current_line,
+                            column_pos + 2,
+                        )?;
 
                         let value = match Value::string_utf8_from_string_utf8_literal(unescaped_str)
                         {
@@ -410,7 +494,11 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
                     }
                 }?;
 
-                result.push((token, current_line, column_pos));
+                let byte_span = ByteSpan {
+                    start: token_start as u32,
+                    end: munch_index as u32,
+                };
+                result.push((token, current_line, column_pos, byte_span));
                 column_pos += whole_match.end() as u32;
                 did_match = true;
                 break;
@@ -427,25 +515,98 @@ pub fn lex(input: &str) -> ParseResult<Vec<(LexItem, u32, u32)>> {
     }
 }
 
-fn unescape_ascii_chars(escaped_str: String, allow_unicode_escape: bool) -> ParseResult<String> {
+/// Get the line and column of the last character of `token`, which starts at `line` and
+/// `column`.  A token such as a string literal with an escaped line break can span lines.
+fn token_end_position(line: u32, column: u32, token: &str) -> (u32, u32) {
+    match token.rfind('\n') {
+        Some(last_break) => (
+            line + token.matches('\n').count() as u32,
+            (token.len() - last_break - 1) as u32,
+        ),
+        None => (line, column + token.len() as u32 - 1),
+    }
+}
+
+/// Build a parse error whose diagnostic points at columns [start_column, end_column] of `line`.
+fn located_error(err: ParseErrors, line: u32, start_column: u32, end_column: u32) -> ParseError {
+    let mut err = ParseError::new(err);
+    err.diagnostic.spans = vec![Span {
+        start_line: line,
+        start_column,
+        end_line: line,
+        end_column,
+    }];
+    err
+}
+
+/// Process the escape sequences in the contents of a string literal.  `line` and `column` give
+/// the source position of the first character of `escaped_str`, so that an invalid escape can
+/// be reported at its location.
+fn unescape_ascii_chars(
+    escaped_str: String,
+    allow_unicode_escape: bool,
+    epoch: StacksEpochId,
+    line: u32,
+    column: u32,
+) -> ParseResult<String> {
     let mut unescaped_str = String::new();
-    let mut chars = escaped_str.chars().into_iter();
-    while let Some(char) = chars.next() {
+    // string literals only contain printable ASCII characters, so char offsets are columns
+    let mut chars = escaped_str.chars().enumerate();
+    while let Some((offset, char)) = chars.next() {
         if char == '\\' {
-            if let Some(next) = chars.next() {
+            let escape_column = column + offset as u32;
+            if let Some((_, next)) = chars.next() {
                 match next {
                     // ASCII escapes based on Rust list (https://doc.rust-lang.org/reference/tokens.html#ascii-escapes)
                     '\\' => unescaped_str.push('\\'),
+                    // a line break in the source, escaped to continue the literal on the next line
+                    '\n' => unescaped_str.push('\n'),
                     '\"' => unescaped_str.push('\"'),
                     'n' => unescaped_str.push('\n'),
                     't' => unescaped_str.push('\t'),
                     'r' => unescaped_str.push('\r'),
                     '0' => unescaped_str.push('\0'),
+                    // 7-bit character code escape, e.g. \x41, from Stacks 2.1
+                    'x' if epoch >= StacksEpochId::Epoch21 => {
+                        let digits: String = chars.by_ref().take(2).map(|(_, c)| c).collect();
+                        let escape_end = escape_column + 1 + digits.len() as u32;
+                        if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                            return Err(located_error(
+                                ParseErrors::InvalidEscaping,
+                                line,
+                                escape_column,
+                                escape_end,
+                            ));
+                        }
+                        match u8::from_str_radix(&digits, 16) {
+                            Ok(code) if code <= 0x7f => unescaped_str.push(code as char),
+                            _ => {
+                                return Err(located_error(
+                                    ParseErrors::InvalidEscaping,
+                                    line,
+                                    escape_column,
+                                    escape_end,
+                                ))
+                            }
+                        }
+                    }
                     'u' if allow_unicode_escape == true => unescaped_str.push_str("\\u"),
-                    _ => return Err(ParseError::new(ParseErrors::InvalidEscaping)),
+                    _ => {
+                        return Err(located_error(
+                            ParseErrors::InvalidEscaping,
+                            line,
+                            escape_column,
+                            escape_column + 1,
+                        ))
+                    }
                 }
             } else {
-                return Err(ParseError::new(ParseErrors::InvalidEscaping));
+                return Err(located_error(
+                    ParseErrors::InvalidEscaping,
+                    line,
+                    escape_column,
+                    escape_column,
+                ));
             }
         } else {
             unescaped_str.push(char);
@@ -461,7 +622,7 @@ enum ParseStackItem {
 }
 
 fn handle_expression(
-    parse_stack: &mut Vec<(Vec<ParseStackItem>, u32, u32, ParseContext)>,
+    parse_stack: &mut Vec<(Vec<ParseStackItem>, u32, u32, u32, ParseContext)>,
     outputs: &mut Vec<PreSymbolicExpression>,
     expr: PreSymbolicExpression,
 ) {
@@ -469,25 +630,35 @@ fn handle_expression(
         // no open lists on stack, add current to outputs.
         None => outputs.push(expr),
         // there's an open list or tuple on the stack.
-        Some((ref mut list, _, _, _)) => list.push(ParseStackItem::Expression(expr)),
+        Some((ref mut list, ..)) => list.push(ParseStackItem::Expression(expr)),
     }
 }
 
-pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSymbolicExpression>> {
+pub fn parse_lexed(
+    mut input: Vec<(LexItem, u32, u32, ByteSpan)>,
+) -> ParseResult<Vec<PreSymbolicExpression>> {
     let mut parse_stack = Vec::new();
 
     let mut output_list = Vec::new();
 
-    for (item, line_pos, column_pos) in input.drain(..) {
+    for (item, line_pos, column_pos, byte_span) in input.drain(..) {
         match item {
             LexItem::LeftParen => {
                 // start new list.
                 let new_list = Vec::new();
-                parse_stack.push((new_list, line_pos, column_pos, ParseContext::CollectList));
+                parse_stack.push((
+                    new_list,
+                    line_pos,
+                    column_pos,
+                    byte_span.start,
+                    ParseContext::CollectList,
+                ));
             }
             LexItem::RightParen => {
                 // end current list.
-                if let Some((list, start_line, start_column, parse_context)) = parse_stack.pop() {
+                if let Some((list, start_line, start_column, start_byte, parse_context)) =
+                    parse_stack.pop()
+                {
                     match parse_context {
                         ParseContext::CollectList => {
                             let checked_list: ParseResult<Box<[PreSymbolicExpression]>> = list
@@ -505,6 +676,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                             let checked_list = checked_list?;
                             let mut pre_expr = PreSymbolicExpression::list(checked_list);
                             pre_expr.set_span(start_line, start_column, line_pos, column_pos);
+                            pre_expr.set_byte_span(start_byte, byte_span.end);
                             handle_expression(&mut parse_stack, &mut output_list, pre_expr);
                         }
                         ParseContext::CollectTuple => {
@@ -524,15 +696,26 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                         "Closing parenthesis expected ({}, {})",
                         line_pos, column_pos
                     );
-                    return Err(ParseError::new(ParseErrors::ClosingParenthesisUnexpected));
+                    return Err(located_error(
+                        ParseErrors::ClosingParenthesisUnexpected,
+                        line_pos,
+                        column_pos,
+                        column_pos,
+                    ));
                 }
             }
             LexItem::LeftCurly => {
                 let new_list = Vec::new();
-                parse_stack.push((new_list, line_pos, column_pos, ParseContext::CollectTuple));
+                parse_stack.push((
+                    new_list,
+                    line_pos,
+                    column_pos,
+                    byte_span.start,
+                    ParseContext::CollectTuple,
+                ));
             }
             LexItem::RightCurly => {
-                if let Some((tuple_list, start_line, start_column, parse_context)) =
+                if let Some((tuple_list, start_line, start_column, start_byte, parse_context)) =
                     parse_stack.pop()
                 {
                     match parse_context {
@@ -569,6 +752,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                             let mut pre_expr =
                                 PreSymbolicExpression::tuple(checked_list.into_boxed_slice());
                             pre_expr.set_span(start_line, start_column, line_pos, column_pos);
+                            pre_expr.set_byte_span(start_byte, byte_span.end);
                             handle_expression(&mut parse_stack, &mut output_list, pre_expr);
                         }
                         ParseContext::CollectList => {
@@ -588,7 +772,12 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                         "Closing tuple literal unexpected ({}, {})",
                         line_pos, column_pos
                     );
-                    return Err(ParseError::new(ParseErrors::ClosingTupleLiteralUnexpected));
+                    return Err(located_error(
+                        ParseErrors::ClosingTupleLiteralUnexpected,
+                        line_pos,
+                        column_pos,
+                        column_pos,
+                    ));
                 }
             }
             LexItem::Variable(value) => {
@@ -598,6 +787,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                 })?;
                 let mut pre_expr = PreSymbolicExpression::atom(value);
                 pre_expr.set_span(line_pos, column_pos, line_pos, end_column);
+                pre_expr.set_byte_span(byte_span.start, byte_span.end);
                 handle_expression(&mut parse_stack, &mut output_list, pre_expr);
             }
             LexItem::LiteralValue(length, value) => {
@@ -608,6 +798,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                 }
                 let mut pre_expr = PreSymbolicExpression::atom_value(value);
                 pre_expr.set_span(line_pos, column_pos, line_pos, end_column);
+                pre_expr.set_byte_span(byte_span.start, byte_span.end);
                 handle_expression(&mut parse_stack, &mut output_list, pre_expr);
             }
             LexItem::SugaredContractIdentifier(length, value) => {
@@ -618,6 +809,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                 }
                 let mut pre_expr = PreSymbolicExpression::sugared_contract_identifier(value);
                 pre_expr.set_span(line_pos, column_pos, line_pos, end_column);
+                pre_expr.set_byte_span(byte_span.start, byte_span.end);
                 handle_expression(&mut parse_stack, &mut output_list, pre_expr);
             }
             LexItem::SugaredFieldIdentifier(length, contract_name, name) => {
@@ -629,6 +821,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                 let mut pre_expr =
                     PreSymbolicExpression::sugared_field_identifier(contract_name, name);
                 pre_expr.set_span(line_pos, column_pos, line_pos, end_column);
+                pre_expr.set_byte_span(byte_span.start, byte_span.end);
                 handle_expression(&mut parse_stack, &mut output_list, pre_expr);
             }
             LexItem::FieldIdentifier(length, trait_identifier) => {
@@ -639,6 +832,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                 }
                 let mut pre_expr = PreSymbolicExpression::field_identifier(trait_identifier);
                 pre_expr.set_span(line_pos, column_pos, line_pos, end_column);
+                pre_expr.set_byte_span(byte_span.start, byte_span.end);
                 handle_expression(&mut parse_stack, &mut output_list, pre_expr);
             }
             LexItem::TraitReference(_length, value) => {
@@ -648,6 +842,7 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
                 })?;
                 let mut pre_expr = PreSymbolicExpression::trait_reference(value);
                 pre_expr.set_span(line_pos, column_pos, line_pos, end_column);
+                pre_expr.set_byte_span(byte_span.start, byte_span.end);
                 handle_expression(&mut parse_stack, &mut output_list, pre_expr);
             }
             LexItem::ColonSeparator => {
@@ -673,8 +868,13 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
     // check unfinished stack:
     if parse_stack.len() > 0 {
         let mut error = ParseError::new(ParseErrors::ClosingParenthesisExpected);
-        if let Some((_list, start_line, start_column, _parse_context)) = parse_stack.pop() {
-            error.diagnostic.add_span(start_line, start_column, 0, 0);
+        if let Some((_list, start_line, start_column, _start_byte, _parse_context)) =
+            parse_stack.pop()
+        {
+            // point at the innermost opening paren that was never closed
+            error
+                .diagnostic
+                .add_span(start_line, start_column, start_line, start_column);
             debug!(
                 "Unfinished stack: {} items remaining starting at ({}, {})",
                 parse_stack.len() + 1,
@@ -688,16 +888,90 @@ pub fn parse_lexed(mut input: Vec<(LexItem, u32, u32)>) -> ParseResult<Vec<PreSy
     }
 }
 
+const UTF8_BOM: char = '\u{feff}';
+
+/// Strip a leading UTF-8 byte order mark and any trailing NUL bytes from `input`, which some
+/// editors add when saving a file, and return what's left, the byte offset in `input` where it
+/// starts, and a warning for each.  Editors don't display a BOM, so the line/column spans of
+/// the remaining source are still the ones its author sees.
+fn strip_editor_artifacts(input: &str) -> (&str, usize, Vec<Diagnostic>) {
+    let mut warnings = vec![];
+    let mut stripped = input;
+    let mut byte_offset = 0;
+
+    if let Some(rest) = stripped.strip_prefix(UTF8_BOM) {
+        warnings.push(Diagnostic::warning(
+            "ignored UTF-8 byte order mark at byte offset 0".to_string(),
+        ));
+        stripped = rest;
+        byte_offset = UTF8_BOM.len_utf8();
+    }
+
+    let content = stripped.trim_end_matches('\0');
+    if content.len() < stripped.len() {
+        let offset = input.len() - (stripped.len() - content.len());
+        let mut warning = Diagnostic::warning(format!(
+            "ignored {} trailing NUL byte(s) starting at byte offset {}",
+            stripped.len() - content.len(),
+            offset
+        ));
+        let line = content.matches('\n').count() as u32 + 1;
+        let last_line = match content.rfind('\n') {
+            Some(ix) => &content[ix + 1..],
+            None => content,
+        };
+        let column = last_line.chars().count() as u32 + 1;
+        warning.add_span(line, column, line, column);
+        warnings.push(warning);
+        stripped = content;
+    }
+
+    (stripped, byte_offset, warnings)
+}
+
+/// Legacy function: parse `input` with the rules of Stacks 2.05
 pub fn parse(input: &str) -> ParseResult<Vec<PreSymbolicExpression>> {
-    let lexed = lex(input)?;
-    parse_lexed(lexed)
+    parse_in_epoch(input, StacksEpochId::Epoch2_05)
+}
+
+pub fn parse_in_epoch(
+    input: &str,
+    epoch: StacksEpochId,
+) -> ParseResult<Vec<PreSymbolicExpression>> {
+    parse_with_warnings(input, epoch).map(|(pre_expressions, _)| pre_expressions)
+}
+
+/// Parse `input` with the rules of `epoch`, also returning non-fatal warnings about the source.
+/// From Stacks 2.1, a leading byte order mark or trailing NUL bytes are ignored with a warning;
+/// before that, they are parse errors.
+pub fn parse_with_warnings(
+    input: &str,
+    epoch: StacksEpochId,
+) -> ParseResult<(Vec<PreSymbolicExpression>, Vec<Diagnostic>)> {
+    let (input, byte_offset, warnings) = if epoch >= StacksEpochId::Epoch21 {
+        strip_editor_artifacts(input)
+    } else {
+        (input, 0, vec![])
+    };
+    let mut lexed = lex_in_epoch(input, epoch)?;
+    // keep byte spans relative to the caller's input, not the stripped source
+    for (_, _, _, byte_span) in lexed.iter_mut() {
+        byte_span.start += byte_offset as u32;
+        byte_span.end += byte_offset as u32;
+    }
+    Ok((parse_lexed(lexed)?, warnings))
 }
 
 #[cfg(test)]
 mod test {
+    use crate::types::StacksEpochId;
     use crate::vm::ast;
     use crate::vm::ast::errors::{ParseError, ParseErrors};
-    use crate::vm::representations::{PreSymbolicExpression, PreSymbolicExpressionType};
+    use crate::vm::ast::types::strip_pre_expression_byte_spans;
+    use crate::vm::diagnostic::Level;
+    use crate::vm::representations::{
+        ByteSpan, PreSymbolicExpression, PreSymbolicExpressionType, Span,
+    };
     use crate::vm::types::TraitIdentifier;
     use crate::vm::types::{
         CharType, PrincipalData, QualifiedContractIdentifier, SequenceData, Value,
@@ -751,6 +1025,15 @@ mod test {
         e
     }
 
+    /// Clear the byte spans of parsed expressions, for comparing them against expressions built
+    ///  with line/column spans only.
+    fn without_byte_spans(mut pre_exprs: Vec<PreSymbolicExpression>) -> Vec<PreSymbolicExpression> {
+        pre_exprs
+            .iter_mut()
+            .for_each(strip_pre_expression_byte_spans);
+        pre_exprs
+    }
+
     #[test]
     fn test_parse_let_expression() {
         // This test includes some assertions ont the spans of each atom / atom_value / list, which makes indentation important.
@@ -851,7 +1134,7 @@ mod test {
             make_atom("y", 6, 15, 6, 15),
         ];
 
-        let parsed = ast::parser::parse(&input);
+        let parsed = ast::parser::parse(&input).map(without_byte_spans);
         assert_eq!(
             Ok(program),
             parsed,
@@ -875,7 +1158,7 @@ mod test {
             ),
         ];
 
-        let parsed = ast::parser::parse(&input);
+        let parsed = ast::parser::parse(&input).map(without_byte_spans);
         assert_eq!(
             Ok(program),
             parsed,
@@ -896,7 +1179,7 @@ mod test {
                 make_atom_value(Value::Int(1337), 1, 6, 1, 9),
             ]),
         )];
-        let parsed = ast::parser::parse(&input);
+        let parsed = ast::parser::parse(&input).map(without_byte_spans);
         assert_eq!(Ok(program), parsed, "Should match expected tuple literal");
     }
 
@@ -1231,4 +1514,428 @@ mod test {
             }
         );
     }
+
+    fn parse_ascii_literal(input: &str) -> Vec<u8> {
+        match ast::parser::parse_in_epoch(input, StacksEpochId::Epoch21).unwrap()[0].pre_expr {
+            PreSymbolicExpressionType::AtomValue(Value::Sequence(SequenceData::String(
+                CharType::ASCII(ref v),
+            ))) => v.data.clone(),
+            ref other => panic!("Expected an ASCII string literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_ascii_string_escapes() {
+        assert_eq!(parse_ascii_literal(r#""line1\nline2""#), b"line1\nline2");
+        assert_eq!(parse_ascii_literal(r#""a\tb""#), b"a\tb");
+        assert_eq!(parse_ascii_literal(r#""back\\slash""#), b"back\\slash");
+        assert_eq!(parse_ascii_literal(r#""quote: \"""#), b"quote: \"");
+        assert_eq!(parse_ascii_literal(r#""\x41\x7a-\x5C""#), b"Az-\\");
+
+        // non-ASCII results are still rejected
+        assert_eq!(
+            ast::parser::parse_in_epoch(r#""\x80""#, StacksEpochId::Epoch21)
+                .unwrap_err()
+                .err,
+            ParseErrors::InvalidEscaping
+        );
+        // control characters are not legal string-ascii contents
+        assert_eq!(
+            ast::parser::parse_in_epoch(r#""\x01""#, StacksEpochId::Epoch21)
+                .unwrap_err()
+                .err,
+            ParseErrors::InvalidCharactersDetected
+        );
+        // malformed hex escapes
+        assert_eq!(
+            ast::parser::parse_in_epoch(r#""\x4""#, StacksEpochId::Epoch21)
+                .unwrap_err()
+                .err,
+            ParseErrors::InvalidEscaping
+        );
+        assert_eq!(
+            ast::parser::parse_in_epoch(r#""\xZZ""#, StacksEpochId::Epoch21)
+                .unwrap_err()
+                .err,
+            ParseErrors::InvalidEscaping
+        );
+
+        // before 2.1, hex escapes are invalid
+        assert_eq!(
+            ast::parser::parse(r#""\x41""#).unwrap_err().err,
+            ParseErrors::InvalidEscaping
+        );
+        assert_eq!(
+            ast::parser::parse_in_epoch(r#""\x41""#, StacksEpochId::Epoch2_05)
+                .unwrap_err()
+                .err,
+            ParseErrors::InvalidEscaping
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_escape_span() {
+        let err = ast::parser::parse("(foo\n  \"ab\\qc\")").unwrap_err();
+        assert_eq!(err.err, ParseErrors::InvalidEscaping);
+        assert_eq!(
+            err.diagnostic.spans,
+            vec![Span {
+                start_line: 2,
+                start_column: 6,
+                end_line: 2,
+                end_column: 7
+            }]
+        );
+
+        let err = ast::parser::parse_in_epoch(r#"u"\x4g""#, StacksEpochId::Epoch21).unwrap_err();
+        assert_eq!(err.err, ParseErrors::InvalidEscaping);
+        assert_eq!(
+            err.diagnostic.spans,
+            vec![Span {
+                start_line: 1,
+                start_column: 3,
+                end_line: 1,
+                end_column: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_name_length_limit() {
+        let longest_name = "a".repeat(128);
+        let program = format!("({} 1)", longest_name);
+        let parsed = ast::parser::parse(&program).unwrap();
+        assert_eq!(
+            parsed[0].match_list().unwrap()[0]
+                .match_atom()
+                .unwrap()
+                .as_str(),
+            longest_name
+        );
+
+        let too_long_name = format!("{}-x", "a".repeat(127));
+        let program = format!("(foo\n  {} 1)", too_long_name);
+        let err = ast::parser::parse(&program).unwrap_err();
+        assert_eq!(err.err, ParseErrors::NameTooLong(129));
+        assert_eq!(
+            err.diagnostic.spans,
+            vec![Span {
+                start_line: 2,
+                start_column: 3,
+                end_line: 2,
+                end_column: 131
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_with_bom() {
+        let contract = "(define-constant x 1)\n(foo x)";
+        let with_bom = format!("\u{feff}{}", contract);
+
+        // the BOM is stripped without shifting any line/column spans
+        let expected = without_byte_spans(ast::parser::parse(contract).unwrap());
+        assert_eq!(
+            without_byte_spans(
+                ast::parser::parse_in_epoch(&with_bom, StacksEpochId::Epoch21).unwrap()
+            ),
+            expected
+        );
+
+        let (parsed, warnings) =
+            ast::parser::parse_with_warnings(&with_bom, StacksEpochId::Epoch21).unwrap();
+        // ...but byte spans are still offsets into the original input
+        assert_eq!(parsed[1].byte_span(), ByteSpan { start: 25, end: 32 });
+        assert_eq!(
+            &with_bom[parsed[1].byte_span().start as usize..parsed[1].byte_span().end as usize],
+            "(foo x)"
+        );
+        assert_eq!(without_byte_spans(parsed), expected);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].level, Level::Warning);
+        assert!(warnings[0]
+            .message
+            .contains("byte order mark at byte offset 0"));
+
+        let (_, warnings) =
+            ast::parser::parse_with_warnings(contract, StacksEpochId::Epoch21).unwrap();
+        assert!(warnings.is_empty());
+
+        // before 2.1, a BOM is still a parse error
+        assert!(ast::parser::parse(&with_bom).is_err());
+        assert!(ast::parser::parse_in_epoch(&with_bom, StacksEpochId::Epoch2_05).is_err());
+    }
+
+    #[test]
+    fn test_parse_with_trailing_nul() {
+        let contract = "(define-constant x 1)\n  (foo x)";
+        let with_nul = format!("{}\0\0", contract);
+
+        let expected = ast::parser::parse(contract).unwrap();
+        let (parsed, warnings) =
+            ast::parser::parse_with_warnings(&with_nul, StacksEpochId::Epoch21).unwrap();
+        assert_eq!(parsed, expected);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].level, Level::Warning);
+        assert!(warnings[0]
+            .message
+            .contains("2 trailing NUL byte(s) starting at byte offset 31"));
+        assert_eq!(
+            warnings[0].spans,
+            vec![Span {
+                start_line: 2,
+                start_column: 10,
+                end_line: 2,
+                end_column: 10
+            }]
+        );
+
+        // a NUL anywhere else is still an error
+        assert!(ast::parser::parse_in_epoch("(foo \0 x)", StacksEpochId::Epoch21).is_err());
+
+        // before 2.1, trailing NULs are still a parse error
+        assert!(ast::parser::parse(&with_nul).is_err());
+    }
+
+    #[test]
+    fn test_unbalanced_paren_spans() {
+        // the opening paren of (foo is never closed
+        let err = ast::parser::parse("(foo\n  (bar 1)\n  (bar 2)").unwrap_err();
+        assert_eq!(err.err, ParseErrors::ClosingParenthesisExpected);
+        assert_eq!(
+            err.diagnostic.spans,
+            vec![Span {
+                start_line: 1,
+                start_column: 1,
+                end_line: 1,
+                end_column: 1
+            }]
+        );
+
+        let err = ast::parser::parse("(foo (bar 1)\n  (bar 2)))").unwrap_err();
+        assert_eq!(err.err, ParseErrors::ClosingParenthesisUnexpected);
+        assert_eq!(
+            err.diagnostic.spans,
+            vec![Span {
+                start_line: 2,
+                start_column: 11,
+                end_line: 2,
+                end_column: 11
+            }]
+        );
+
+        let err = ast::parser::parse("{a: 1}}").unwrap_err();
+        assert_eq!(err.err, ParseErrors::ClosingTupleLiteralUnexpected);
+        assert_eq!(
+            err.diagnostic.spans,
+            vec![Span {
+                start_line: 1,
+                start_column: 7,
+                end_line: 1,
+                end_column: 7
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_byte_spans() {
+        let input = "(define-constant x u1)\n;; a comment\n(print {a: x, b: \"hi\"})";
+        let parsed = ast::parser::parse(input).unwrap();
+        let text = |pre_expr: &PreSymbolicExpression| {
+            let byte_span = pre_expr.byte_span();
+            &input[byte_span.start as usize..byte_span.end as usize]
+        };
+
+        let define = parsed[0].match_list().unwrap();
+        assert_eq!(parsed[0].byte_span(), ByteSpan { start: 0, end: 22 });
+        assert_eq!(text(&parsed[0]), "(define-constant x u1)");
+        assert_eq!(text(&define[0]), "define-constant");
+        assert_eq!(define[1].byte_span(), ByteSpan { start: 17, end: 18 });
+        assert_eq!(text(&define[2]), "u1");
+
+        let print = parsed[1].match_list().unwrap();
+        assert_eq!(parsed[1].byte_span(), ByteSpan { start: 36, end: 59 });
+        assert_eq!(text(&parsed[1]), "(print {a: x, b: \"hi\"})");
+        assert_eq!(text(&print[1]), "{a: x, b: \"hi\"}");
+        let tuple = match print[1].pre_expr {
+            PreSymbolicExpressionType::Tuple(ref items) => items,
+            _ => panic!("Expected a tuple literal"),
+        };
+        assert_eq!(text(&tuple[1]), "x");
+        assert_eq!(text(&tuple[3]), "\"hi\"");
+
+        // byte spans survive into the expanded AST
+        let exprs = ast::parse(&QualifiedContractIdentifier::transient(), input).unwrap();
+        assert_eq!(exprs[1].byte_span(), ByteSpan { start: 36, end: 59 });
+        assert_eq!(
+            exprs[0].match_list().unwrap()[1].byte_span(),
+            ByteSpan { start: 17, end: 18 }
+        );
+    }
+
+    #[test]
+    fn test_parse_negative_int_literals() {
+        let negative = ast::parser::parse("(+ -5 3)").unwrap();
+        assert_eq!(
+            without_byte_spans(negative.clone()),
+            vec![make_list(
+                1,
+                1,
+                1,
+                8,
+                Box::new([
+                    make_atom("+", 1, 2, 1, 2),
+                    make_atom_value(Value::Int(-5), 1, 4, 1, 5),
+                    make_atom_value(Value::Int(3), 1, 7, 1, 7),
+                ])
+            )]
+        );
+        let args = negative[0].match_list().unwrap();
+        assert_eq!(args[1].byte_span(), ByteSpan { start: 3, end: 5 });
+
+        // a lone `-` is the subtraction atom, even when followed by an int literal
+        let subtraction = ast::parser::parse("(- 5 3)").unwrap();
+        assert_eq!(
+            without_byte_spans(subtraction),
+            vec![make_list(
+                1,
+                1,
+                1,
+                7,
+                Box::new([
+                    make_atom("-", 1, 2, 1, 2),
+                    make_atom_value(Value::Int(5), 1, 4, 1, 4),
+                    make_atom_value(Value::Int(3), 1, 6, 1, 6),
+                ])
+            )]
+        );
+
+        let both = ast::parser::parse("(- -5)").unwrap();
+        let args = both[0].match_list().unwrap();
+        assert_eq!(args[0].match_atom().unwrap().as_str(), "-");
+        assert_eq!(args[1].match_atom_value(), Some(&Value::Int(-5)));
+
+        // a minus sign must be immediately followed by the digits
+        assert!(ast::parser::parse("(+ - 5 3)").unwrap()[0]
+            .match_list()
+            .unwrap()[1]
+            .match_atom()
+            .is_some());
+    }
+
+    #[test]
+    fn test_separator_expected_spans() {
+        let span = |start_line, start_column, end_line, end_column| Span {
+            start_line,
+            start_column,
+            end_line,
+            end_column,
+        };
+
+        // `023ab13` lexes as `023` followed by `ab13`
+        let err = ast::parser::parse("(let ((023ab13 1)))").unwrap_err();
+        assert_eq!(err.err, ParseErrors::SeparatorExpected("ab13".to_string()));
+        assert_eq!(err.diagnostic.spans, vec![span(1, 8, 1, 14)]);
+
+        let err = ast::parser::parse("(foo\n  bar\"baz\")").unwrap_err();
+        assert_eq!(
+            err.err,
+            ParseErrors::SeparatorExpected("\"baz\"".to_string())
+        );
+        assert_eq!(err.diagnostic.spans, vec![span(2, 3, 2, 10)]);
+
+        // from 2.1, a string with an escaped line break ends on a later line
+        let err =
+            ast::parser::parse_in_epoch("(foo bar\"a\\\nb\")", StacksEpochId::Epoch21).unwrap_err();
+        assert_eq!(err.diagnostic.spans, vec![span(1, 6, 2, 2)]);
+
+        let err = ast::parser::parse("{ a:b }").unwrap_err();
+        assert_eq!(
+            err.err,
+            ParseErrors::SeparatorExpectedAfterColon("b".to_string())
+        );
+        assert_eq!(err.diagnostic.spans, vec![span(1, 4, 1, 5)]);
+    }
+
+    #[test]
+    fn test_parse_uint_and_int_literals() {
+        let uints = ast::parser::parse("(+ u5 u3)").unwrap();
+        assert_eq!(
+            without_byte_spans(uints),
+            vec![make_list(
+                1,
+                1,
+                1,
+                9,
+                Box::new([
+                    make_atom("+", 1, 2, 1, 2),
+                    make_atom_value(Value::UInt(5), 1, 4, 1, 5),
+                    make_atom_value(Value::UInt(3), 1, 7, 1, 8),
+                ])
+            )]
+        );
+
+        let ints = ast::parser::parse("(+ 5 3)").unwrap();
+        assert_eq!(
+            without_byte_spans(ints),
+            vec![make_list(
+                1,
+                1,
+                1,
+                7,
+                Box::new([
+                    make_atom("+", 1, 2, 1, 2),
+                    make_atom_value(Value::Int(5), 1, 4, 1, 4),
+                    make_atom_value(Value::Int(3), 1, 6, 1, 6),
+                ])
+            )]
+        );
+
+        // a `u` without digits is just an atom
+        let atom = ast::parser::parse("(foo u)").unwrap();
+        assert_eq!(
+            without_byte_spans(atom),
+            vec![make_list(
+                1,
+                1,
+                1,
+                7,
+                Box::new([make_atom("foo", 1, 2, 1, 4), make_atom("u", 1, 6, 1, 6)])
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_multi_line_string_literal() {
+        let input = "(define-constant s \"ab\\\ncd\")\n(define-constant t u1)";
+        // before 2.1, a line break can't be escaped
+        assert!(ast::parser::parse(input).is_err());
+
+        let parsed = ast::parser::parse_in_epoch(input, StacksEpochId::Epoch21).unwrap();
+        assert_eq!(parsed.len(), 2);
+
+        let first = parsed[0].match_list().unwrap();
+        assert_eq!(
+            first[2].match_atom_value().unwrap(),
+            &Value::string_ascii_from_bytes(b"ab\ncd".to_vec()).unwrap()
+        );
+
+        // the define after the literal starts on line 3, not line 2
+        assert_eq!(parsed[1].span.start_line, 3);
+        assert_eq!(parsed[1].span.start_column, 1);
+        let second = parsed[1].match_list().unwrap();
+        assert_eq!(second[2].span.start_line, 3);
+        assert_eq!(second[2].span.start_column, 20);
+
+        // blank lines between expressions are all counted too
+        let spaced =
+            ast::parser::parse_in_epoch("(+ 1 2)\n\n\n  (+ 3 4)", StacksEpochId::Epoch21).unwrap();
+        assert_eq!(spaced[1].span.start_line, 4);
+        assert_eq!(spaced[1].span.start_column, 3);
+
+        // ...but before 2.1, lines are still tracked one line break per token
+        let spaced = ast::parser::parse("(+ 1 2)\n\n\n  (+ 3 4)").unwrap();
+        assert_eq!(spaced[1].span.start_line, 3);
+        assert_eq!(spaced[1].span.start_column, 1);
+    }
 }
@@ -0,0 +1,124 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::vm::ast::errors::{ParseError, ParseErrors, ParseResult};
+use crate::vm::ast::types::{BuildASTPass, ContractAST};
+use crate::vm::functions::define::DefineFunctions;
+use crate::vm::is_reserved;
+use crate::vm::representations::PreSymbolicExpression;
+
+/// Rejects traits, trait aliases and assets whose names are reserved words.  (The names of
+///  functions, constants and data are checked when the contract is analyzed or evaluated.)
+///  This pass only runs from Stacks 2.1.
+pub struct ReservedNameChecker;
+
+impl BuildASTPass for ReservedNameChecker {
+    fn run_pass(contract_ast: &mut ContractAST) -> ParseResult<()> {
+        for expr in contract_ast.pre_expressions.iter() {
+            check_defined_name(expr)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_defined_name(expr: &PreSymbolicExpression) -> ParseResult<()> {
+    let (define_name, args) = match expr.match_list().and_then(|list| list.split_first()) {
+        Some(define) => define,
+        None => return Ok(()),
+    };
+    match define_name
+        .match_atom()
+        .and_then(|name| DefineFunctions::lookup_by_name(name))
+    {
+        Some(DefineFunctions::Trait)
+        | Some(DefineFunctions::UseTrait)
+        | Some(DefineFunctions::FungibleToken)
+        | Some(DefineFunctions::NonFungibleToken) => {}
+        _ => return Ok(()),
+    }
+
+    let name_expr = match args.first() {
+        Some(name_expr) => name_expr,
+        None => return Ok(()),
+    };
+    match name_expr.match_atom() {
+        Some(name) if is_reserved(name) => {
+            let mut err = ParseError::new(ParseErrors::ReservedName(name.to_string()));
+            err.set_pre_expression(name_expr);
+            Err(err)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::StacksEpochId;
+    use crate::vm::ast::errors::ParseErrors;
+    use crate::vm::ast::{build_ast_with_stage, BuildAstStage};
+    use crate::vm::types::QualifiedContractIdentifier;
+
+    fn check_rejected(contract: &str, name: &str, columns: (u32, u32)) {
+        // before 2.1, these names are accepted
+        build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            contract,
+            &mut (),
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap();
+
+        let (stage, err) = build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            contract,
+            &mut (),
+            StacksEpochId::Epoch21,
+        )
+        .unwrap_err();
+        assert_eq!(stage, BuildAstStage::ReservedNameCheck);
+        assert_eq!(err.err, ParseErrors::ReservedName(name.to_string()));
+        let span = &err.diagnostic.spans[0];
+        assert_eq!((span.start_column, span.end_column), columns);
+    }
+
+    #[test]
+    fn test_reserved_trait_and_asset_names() {
+        check_rejected(
+            "(use-trait block-height 'S1G2081040G2081040G2081040G208105NK8PE5.tokens.ft-trait)",
+            "block-height",
+            (12, 23),
+        );
+        check_rejected(
+            "(define-trait tx-sender ((foo () (response uint uint))))",
+            "tx-sender",
+            (15, 23),
+        );
+        check_rejected("(define-non-fungible-token map uint)", "map", (28, 30));
+        check_rejected("(define-fungible-token true)", "true", (24, 27));
+
+        let contract =
+            "(use-trait ft-trait 'S1G2081040G2081040G2081040G208105NK8PE5.tokens.ft-trait)
+            (define-non-fungible-token badge uint)
+            (define-fungible-token gold)";
+        build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            contract,
+            &mut (),
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+    }
+}
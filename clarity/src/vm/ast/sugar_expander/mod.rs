@@ -64,8 +64,9 @@ impl SugarExpander {
     ) -> ParseResult<Vec<SymbolicExpression>> {
         let mut expressions = Vec::new();
 
-        for pre_expr in pre_exprs_iter {
-            let mut expr = match pre_expr.pre_expr {
+        for mut pre_expr in pre_exprs_iter {
+            // move the payload out, keeping `pre_expr` around for its span
+            let expr = match pre_expr.take_pre_expr() {
                 PreSymbolicExpressionType::AtomValue(content) => {
                     SymbolicExpression::literal_value(content)
                 }
@@ -116,8 +117,7 @@ impl SugarExpander {
                 }
             };
             // expr.id will be set by the subsequent expression identifier pass.
-            expr.span = pre_expr.span.clone();
-            expressions.push(expr);
+            expressions.push(expr.with_span_of(&pre_expr));
         }
         Ok(expressions)
     }
@@ -505,4 +505,31 @@ mod test {
             "Should match expected symbolic expression"
         );
     }
+
+    #[test]
+    fn test_transform_sugared_contract_identifier_span() {
+        let contract_id = QualifiedContractIdentifier::parse(
+            "S1G2081040G2081040G2081040G208105NK8PE5.contract-a",
+        )
+        .unwrap();
+        let pre_ast = ast::parser::parse("(foo .tokens)").unwrap();
+        let pre_span = pre_ast[0].match_list().unwrap()[1].span.clone();
+
+        let mut contract_ast = ContractAST::new(contract_id.clone(), pre_ast);
+        let expander = SugarExpander::new(contract_id.issuer);
+        expander.run(&mut contract_ast).unwrap();
+
+        let expr = &contract_ast.expressions[0].match_list().unwrap()[1];
+        assert!(expr.match_literal_value().is_some());
+        assert_eq!(expr.span, pre_span);
+        assert_eq!(
+            (
+                expr.span.start_line,
+                expr.span.start_column,
+                expr.span.end_line,
+                expr.span.end_column
+            ),
+            (1, 6, 1, 12)
+        );
+    }
 }
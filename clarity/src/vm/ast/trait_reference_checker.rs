@@ -0,0 +1,185 @@
+// Copyright (C) 2013-2020 Blockstack PBC, a public benefit corporation
+// Copyright (C) 2020 Stacks Open Internet Foundation
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::vm::ast::errors::{ParseError, ParseErrors, ParseResult};
+use crate::vm::ast::types::{BuildASTPass, ContractAST};
+use crate::vm::functions::define::DefineFunctions;
+use crate::vm::representations::SymbolicExpression;
+use crate::vm::representations::SymbolicExpressionType::{List, TraitReference};
+
+/// Rejects trait references outside of the positions where they are allowed: the argument
+///  types of a function's signature, and the method signatures of a trait definition.  This
+///  runs on the expanded expressions, after references to unknown traits have been rejected.
+///  (Trait references in the types of maps, data-vars and other stored data are rejected
+///  when traits are resolved.)
+pub struct TraitReferenceChecker;
+
+impl BuildASTPass for TraitReferenceChecker {
+    fn run_pass(contract_ast: &mut ContractAST) -> ParseResult<()> {
+        for expr in contract_ast.expressions.iter() {
+            check_top_level_expression(expr)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_top_level_expression(expr: &SymbolicExpression) -> ParseResult<()> {
+    let (define_name, args) = match expr.match_list().and_then(|list| list.split_first()) {
+        Some(define) => define,
+        None => return check_no_trait_references(expr),
+    };
+    let define_type = define_name
+        .match_atom()
+        .and_then(|name| DefineFunctions::lookup_by_name(name));
+    match define_type {
+        Some(DefineFunctions::PublicFunction)
+        | Some(DefineFunctions::PrivateFunction)
+        | Some(DefineFunctions::ReadOnlyFunction) => {
+            // the signature's argument types may reference traits, but the body may not
+            let (signature, body) = match args.split_first() {
+                Some(function) => function,
+                None => return Ok(()),
+            };
+            match signature.match_list() {
+                Some(signature) => {
+                    for arg in signature.iter() {
+                        match arg.match_list() {
+                            Some(pair) if pair.len() == 2 => check_no_trait_references(&pair[0])?,
+                            _ => check_no_trait_references(arg)?,
+                        }
+                    }
+                }
+                None => check_no_trait_references(signature)?,
+            }
+            body.iter().try_for_each(check_no_trait_references)
+        }
+        Some(DefineFunctions::Trait) => {
+            // method signatures may reference traits anywhere in their argument types
+            match args.split_first() {
+                Some((name, _)) => check_no_trait_references(name),
+                None => Ok(()),
+            }
+        }
+        _ => args.iter().try_for_each(check_no_trait_references),
+    }
+}
+
+fn check_no_trait_references(expr: &SymbolicExpression) -> ParseResult<()> {
+    match expr.expr {
+        TraitReference(..) => {
+            let mut err = ParseError::new(ParseErrors::TraitReferenceNotAllowed);
+            err.diagnostic.spans = vec![expr.span()];
+            Err(err)
+        }
+        List(ref exprs) => exprs.iter().try_for_each(check_no_trait_references),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::types::StacksEpochId;
+    use crate::vm::analysis::mem_type_check;
+    use crate::vm::ast::errors::ParseErrors;
+    use crate::vm::ast::{build_ast_with_stage, BuildAstStage};
+    use crate::vm::types::QualifiedContractIdentifier;
+
+    fn check_rejected(contract: &str, expected_stage: BuildAstStage, position: (u32, u32, u32)) {
+        let (stage, err) = build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            contract,
+            &mut (),
+            StacksEpochId::Epoch21,
+        )
+        .unwrap_err();
+        assert_eq!(stage, expected_stage);
+        assert_eq!(err.err, ParseErrors::TraitReferenceNotAllowed);
+        let span = &err.diagnostic.spans[0];
+        assert_eq!(
+            (span.start_line, span.start_column, span.end_column),
+            position
+        );
+    }
+
+    #[test]
+    fn test_trait_reference_positions() {
+        let contract = "(define-trait token ((transfer (uint principal) (response bool uint))))
+            (define-trait router ((route (<token> uint) (response bool uint))))
+            (define-public (swap (a <token>) (b <token>) (amount uint))
+              (ok (is-eq amount u0)))";
+        build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            contract,
+            &mut (),
+            StacksEpochId::Epoch21,
+        )
+        .unwrap();
+
+        let header = "(define-trait token ((transfer (uint principal) (response bool uint))))\n";
+        // stored data is rejected while traits are resolved
+        check_rejected(
+            &format!("{}(define-data-var t principal <token>)", header),
+            BuildAstStage::TraitResolution,
+            (2, 30, 34),
+        );
+        check_rejected(
+            &format!("{}(define-constant c <token>)", header),
+            BuildAstStage::TraitResolution,
+            (2, 20, 24),
+        );
+        check_rejected(
+            &format!("{}(define-private (f (t <token>)) <token>)", header),
+            BuildAstStage::TraitReferenceCheck,
+            (2, 33, 37),
+        );
+        check_rejected(
+            &format!("{}(define-private (f (<token> int)) 1)", header),
+            BuildAstStage::TraitReferenceCheck,
+            (2, 21, 25),
+        );
+        check_rejected(
+            &format!("{}(print <token>)", header),
+            BuildAstStage::TraitReferenceCheck,
+            (2, 8, 12),
+        );
+    }
+
+    #[test]
+    fn test_trait_reference_positions_before_2_1() {
+        let header = "(define-trait token ((transfer (uint principal) (response bool uint))))\n";
+        // stored data is still rejected while traits are resolved
+        let (stage, err) = build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            &format!("{}(define-data-var t principal <token>)", header),
+            &mut (),
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap_err();
+        assert_eq!(stage, BuildAstStage::TraitResolution);
+        assert_eq!(err.err, ParseErrors::TraitReferenceNotAllowed);
+
+        // but other misplaced references are left for analysis to reject
+        let contract = format!("{}(print <token>)", header);
+        build_ast_with_stage(
+            &QualifiedContractIdentifier::transient(),
+            &contract,
+            &mut (),
+            StacksEpochId::Epoch2_05,
+        )
+        .unwrap();
+        assert!(mem_type_check(&contract).is_err());
+    }
+}
@@ -198,7 +198,9 @@ impl TraitsResolver {
                     if should_reference {
                         referenced_traits.insert(trait_name.clone(), expression.clone());
                     } else {
-                        return Err(ParseErrors::TraitReferenceNotAllowed.into());
+                        let mut err = ParseError::new(ParseErrors::TraitReferenceNotAllowed);
+                        err.set_pre_expression(expression);
+                        return Err(err);
                     }
                 }
                 Tuple(atoms) => {
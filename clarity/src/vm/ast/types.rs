@@ -15,17 +15,35 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::vm::ast::errors::ParseResult;
-use crate::vm::representations::{PreSymbolicExpression, SymbolicExpression, TraitDefinition};
+use crate::vm::callables::DefineType;
+use crate::vm::diagnostic::Diagnostic;
+use crate::vm::functions::define::DefineFunctionsParsed;
+use crate::vm::representations::{
+    PreSymbolicExpression, PreSymbolicExpressionType, Span, SymbolicExpression,
+    SymbolicExpressionType, TraitDefinition,
+};
 use crate::vm::types::signatures::FunctionSignature;
-use crate::vm::types::{QualifiedContractIdentifier, TraitIdentifier};
+use crate::vm::types::{parse_name_type_pairs, QualifiedContractIdentifier, TraitIdentifier};
 use crate::vm::ClarityName;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::vec::Drain;
 
 pub trait BuildASTPass {
     fn run_pass(contract_ast: &mut ContractAST) -> ParseResult<()>;
 }
 
+/// An object-safe pass over a contract's AST, which can be supplied to
+///  `build_ast_with_passes` to extend the pipeline.  Every `BuildASTPass` is one.
+pub trait ContractASTPass {
+    fn run(&self, contract_ast: &mut ContractAST) -> ParseResult<()>;
+}
+
+impl<P: BuildASTPass> ContractASTPass for P {
+    fn run(&self, contract_ast: &mut ContractAST) -> ParseResult<()> {
+        P::run_pass(contract_ast)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct ContractAST {
     pub contract_identifier: QualifiedContractIdentifier,
@@ -36,6 +54,10 @@ pub struct ContractAST {
     pub implemented_traits: HashSet<TraitIdentifier>,
 }
 
+/// The parent id recorded by `ContractAST::build_parent_map` for top-level expressions.
+///  Expression ids start at 1, so this is never the id of an expression.
+pub const TOP_LEVEL_PARENT_ID: u64 = 0;
+
 impl ContractAST {
     pub fn new(
         contract_identifier: QualifiedContractIdentifier,
@@ -67,6 +89,405 @@ impl ContractAST {
     pub fn get_referenced_trait(&self, name: &str) -> Option<&TraitDefinition> {
         self.referenced_traits.get(name)
     }
+
+    /// Map the id of each expression to the id of its enclosing list, or to
+    ///  `TOP_LEVEL_PARENT_ID` for top-level expressions.  The map is built by walking the
+    ///  expressions on each call, so callers walking upward should build it once and keep it.
+    pub fn build_parent_map(&self) -> HashMap<u64, u64> {
+        let mut parents = HashMap::new();
+        record_parents(&self.expressions, TOP_LEVEL_PARENT_ID, &mut parents);
+        parents
+    }
+
+    /// Zero the source span of every expression and pre-expression in this AST, so that
+    ///  ASTs parsed from differently-formatted copies of the same source compare equal.
+    pub fn strip_spans(&mut self) {
+        for expr in self.expressions.iter_mut() {
+            strip_expression_spans(expr);
+        }
+        for pre_expr in self.pre_expressions.iter_mut() {
+            strip_pre_expression_spans(pre_expr);
+        }
+    }
+
+    /// A copy of this AST with all source spans zeroed (see `strip_spans`).
+    pub fn clone_without_spans(&self) -> ContractAST {
+        let mut contract_ast = self.clone();
+        contract_ast.strip_spans();
+        contract_ast
+    }
+
+    /// Render this AST's expressions back into Clarity source, one top-level expression per
+    ///  line.  Lists that do not fit within `SOURCE_LINE_WIDTH` columns are broken up with one
+    ///  argument per line, indented by two spaces.  The result parses back into this AST
+    ///  (modulo spans).
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        for expr in self.expressions.iter() {
+            write_expression_source(&mut source, expr, 0);
+            source.push('\n');
+        }
+        source
+    }
+
+    /// The name, argument count and kind of each public and read-only function defined at
+    ///  the top level of this contract, in definition order.
+    pub fn public_function_signatures(&self) -> Vec<(ClarityName, usize, DefineType)> {
+        self.public_function_defines()
+            .map(|(name, args, define_type, _)| (name.clone(), args.len(), define_type))
+            .collect()
+    }
+
+    /// Iterate over the top-level `define-public` and `define-read-only` forms, yielding
+    ///  each function's name, argument list, kind, and the define expression itself.
+    fn public_function_defines(
+        &self,
+    ) -> impl Iterator<
+        Item = (
+            &ClarityName,
+            &[SymbolicExpression],
+            DefineType,
+            &SymbolicExpression,
+        ),
+    > {
+        self.expressions.iter().filter_map(|expr| {
+            let (signature, define_type) = match DefineFunctionsParsed::try_parse(expr) {
+                Ok(Some(DefineFunctionsParsed::PublicFunction { signature, .. })) => {
+                    (signature, DefineType::Public)
+                }
+                Ok(Some(DefineFunctionsParsed::ReadOnlyFunction { signature, .. })) => {
+                    (signature, DefineType::ReadOnly)
+                }
+                _ => return None,
+            };
+            let (name, args) = signature.split_first()?;
+            Some((name.match_atom()?, args, define_type, expr))
+        })
+    }
+
+    /// The spans of the statements in the public and read-only functions of this contract
+    ///  that can never run, because they follow a statement in the same `begin` block that
+    ///  always exits early -- e.g. `(asserts! false ...)`, `(unwrap! none ...)` or
+    ///  `(unwrap-err! (ok ...) ...)`.  This only scans the AST; it does not check it.
+    pub fn unreachable_statements(&self) -> Vec<Span> {
+        let mut spans = vec![];
+        for (_, _, _, expr) in self.public_function_defines() {
+            find_unreachable_statements(expr, &mut spans);
+        }
+        spans
+    }
+
+    /// Warnings for each `let` binding in a function body that shadows one of the function's
+    ///  arguments or a binding of an enclosing `let`.  Each warning's spans are those of the
+    ///  shadowing name and then the shadowed name.  This only scans the AST; it does not check
+    ///  it.
+    pub fn shadowing_warnings(&self) -> Vec<Diagnostic> {
+        let mut warnings = vec![];
+        for expr in self.expressions.iter() {
+            let (signature, body) = match DefineFunctionsParsed::try_parse(expr) {
+                Ok(Some(DefineFunctionsParsed::PrivateFunction { signature, body }))
+                | Ok(Some(DefineFunctionsParsed::ReadOnlyFunction { signature, body }))
+                | Ok(Some(DefineFunctionsParsed::PublicFunction { signature, body })) => {
+                    (signature, body)
+                }
+                _ => continue,
+            };
+            let mut scope = HashMap::new();
+            for arg in signature.iter().skip(1) {
+                if let Some(name) = arg.match_list().and_then(|pair| pair.get(0)) {
+                    if let Some(name_str) = name.match_atom() {
+                        scope.insert(name_str.clone(), name.span());
+                    }
+                }
+            }
+            find_shadowed_bindings(body, &scope, &mut warnings);
+        }
+        warnings
+    }
+
+    /// The names of the maps, data-vars and tokens defined at the top level of this
+    ///  contract.  This only scans the define forms; it does not check them.
+    pub fn storage_footprint(&self) -> StorageFootprint {
+        let mut footprint = StorageFootprint::default();
+        for expr in self.expressions.iter() {
+            match DefineFunctionsParsed::try_parse(expr) {
+                Ok(Some(DefineFunctionsParsed::Map { name, .. })) => {
+                    footprint.maps.insert(name.clone());
+                }
+                Ok(Some(DefineFunctionsParsed::PersistedVariable { name, .. })) => {
+                    footprint.data_vars.insert(name.clone());
+                }
+                Ok(Some(DefineFunctionsParsed::BoundedFungibleToken { name, .. }))
+                | Ok(Some(DefineFunctionsParsed::UnboundedFungibleToken { name })) => {
+                    footprint.fungible_tokens.insert(name.clone());
+                }
+                Ok(Some(DefineFunctionsParsed::NonFungibleToken { name, .. })) => {
+                    footprint.non_fungible_tokens.insert(name.clone());
+                }
+                _ => {}
+            }
+        }
+        footprint
+    }
+
+    /// Check that this contract defines every method of a trait as a public or
+    ///  read-only function whose arguments match the trait's signature.
+    /// Return types are not inferred from the AST, so they are left to the type-checker.
+    pub fn check_implements_trait(
+        &self,
+        trait_methods: &BTreeMap<ClarityName, FunctionSignature>,
+    ) -> Vec<TraitConformanceError> {
+        let mut defined = HashMap::new();
+        for (name, args, _, expr) in self.public_function_defines() {
+            defined.insert(name.clone(), (args, expr));
+        }
+
+        let mut errors = vec![];
+        for (method_name, method_signature) in trait_methods.iter() {
+            let (args, expr) = match defined.get(method_name) {
+                Some(entry) => entry,
+                None => {
+                    errors.push(TraitConformanceError::MissingMethod(method_name.clone()));
+                    continue;
+                }
+            };
+            let conforms = match parse_name_type_pairs(args, &mut ()) {
+                Ok(arg_types) => method_signature.check_args_trait_compliance(
+                    arg_types
+                        .into_iter()
+                        .map(|(_, arg_type)| arg_type)
+                        .collect(),
+                ),
+                Err(_) => false,
+            };
+            if !conforms {
+                errors.push(TraitConformanceError::SignatureMismatch(
+                    method_name.clone(),
+                    expr.span(),
+                ));
+            }
+        }
+        errors
+    }
+}
+
+fn record_parents(exprs: &[SymbolicExpression], parent_id: u64, parents: &mut HashMap<u64, u64>) {
+    for expression in exprs.iter() {
+        parents.insert(expression.id, parent_id);
+        if let Some(exprs) = expression.match_list() {
+            record_parents(exprs, expression.id, parents);
+        }
+    }
+}
+
+fn strip_expression_spans(expr: &mut SymbolicExpression) {
+    expr.set_span(0, 0, 0, 0);
+    expr.set_byte_span(0, 0);
+    if let SymbolicExpressionType::List(ref mut exprs) = expr.expr {
+        for expr in exprs.iter_mut() {
+            strip_expression_spans(expr);
+        }
+    }
+}
+
+fn strip_pre_expression_spans(pre_expr: &mut PreSymbolicExpression) {
+    visit_pre_expressions_mut(pre_expr, &mut |pre_expr| {
+        pre_expr.set_span(0, 0, 0, 0);
+        pre_expr.set_byte_span(0, 0);
+    });
+}
+
+/// Zero the byte span of `pre_expr` and of every pre-expression nested in it, leaving the
+///  line/column spans in place.
+pub(crate) fn strip_pre_expression_byte_spans(pre_expr: &mut PreSymbolicExpression) {
+    visit_pre_expressions_mut(pre_expr, &mut |pre_expr| pre_expr.set_byte_span(0, 0));
+}
+
+fn visit_pre_expressions_mut(
+    pre_expr: &mut PreSymbolicExpression,
+    visit: &mut dyn FnMut(&mut PreSymbolicExpression),
+) {
+    visit(pre_expr);
+    match pre_expr.pre_expr {
+        PreSymbolicExpressionType::List(ref mut pre_exprs)
+        | PreSymbolicExpressionType::Tuple(ref mut pre_exprs) => {
+            for pre_expr in pre_exprs.iter_mut() {
+                visit_pre_expressions_mut(pre_expr, visit);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn find_unreachable_statements(expr: &SymbolicExpression, spans: &mut Vec<Span>) {
+    let list = match expr.match_list() {
+        Some(list) => list,
+        None => return,
+    };
+    if let Some("begin") = list
+        .get(0)
+        .and_then(|head| head.match_atom())
+        .map(|name| name.as_str())
+    {
+        let statements = &list[1..];
+        if let Some(exits_at) = statements.iter().position(always_exits_early) {
+            for statement in statements[exits_at + 1..].iter() {
+                spans.push(statement.span());
+            }
+        }
+    }
+    for item in list.iter() {
+        find_unreachable_statements(item, spans);
+    }
+}
+
+/// Does `statement` always end evaluation of its function (with an early return or a runtime
+///  error), no matter what the rest of the contract does?  Only literal arguments are
+///  considered, so this never reports a statement that might fall through.
+fn always_exits_early(statement: &SymbolicExpression) -> bool {
+    let list_head = |expr: &SymbolicExpression| {
+        expr.match_list()
+            .and_then(|list| list.get(0))
+            .and_then(|head| head.match_atom())
+            .map(|name| name.to_string())
+    };
+    let is_atom = |expr: &SymbolicExpression, atom: &str| {
+        expr.match_atom().map(|name| name.as_str()) == Some(atom)
+    };
+    let is_none_or_err = |expr: &SymbolicExpression| {
+        is_atom(expr, "none") || list_head(expr).as_deref() == Some("err")
+    };
+    let is_ok = |expr: &SymbolicExpression| list_head(expr).as_deref() == Some("ok");
+
+    let args = match statement.match_list() {
+        Some(list) if !list.is_empty() => &list[1..],
+        _ => return false,
+    };
+    match (list_head(statement).as_deref(), args.get(0)) {
+        (Some("asserts!"), Some(condition)) => is_atom(condition, "false"),
+        (Some("unwrap!"), Some(input))
+        | (Some("unwrap-panic"), Some(input))
+        | (Some("try!"), Some(input)) => is_none_or_err(input),
+        (Some("unwrap-err!"), Some(input)) | (Some("unwrap-err-panic"), Some(input)) => {
+            is_ok(input)
+        }
+        _ => false,
+    }
+}
+
+fn find_shadowed_bindings(
+    expr: &SymbolicExpression,
+    scope: &HashMap<ClarityName, Span>,
+    warnings: &mut Vec<Diagnostic>,
+) {
+    let list = match expr.match_list() {
+        Some(list) => list,
+        None => return,
+    };
+    let is_let = list
+        .get(0)
+        .and_then(|head| head.match_atom())
+        .map(|name| name.as_str())
+        == Some("let");
+    let bindings = match list.get(1).and_then(|bindings| bindings.match_list()) {
+        Some(bindings) if is_let => bindings,
+        _ => {
+            for item in list.iter() {
+                find_shadowed_bindings(item, scope, warnings);
+            }
+            return;
+        }
+    };
+
+    // binding values are evaluated in the enclosing scope, and the let body in that scope
+    //  extended with the bindings
+    let mut inner_scope = scope.clone();
+    for binding in bindings.iter() {
+        let pair = match binding.match_list() {
+            Some(pair) => pair,
+            None => continue,
+        };
+        if let Some(value) = pair.get(1) {
+            find_shadowed_bindings(value, scope, warnings);
+        }
+        let name = match pair.get(0) {
+            Some(name) => name,
+            None => continue,
+        };
+        let name_str = match name.match_atom() {
+            Some(name_str) => name_str,
+            None => continue,
+        };
+        if let Some(shadowed_span) = scope.get(name_str) {
+            let mut warning = Diagnostic::warning(format!(
+                "let binding '{}' shadows an outer binding",
+                name_str
+            ));
+            warning.spans.push(name.span());
+            warning.spans.push(shadowed_span.clone());
+            warnings.push(warning);
+        }
+        inner_scope.insert(name_str.clone(), name.span());
+    }
+    for body_expr in list[2..].iter() {
+        find_shadowed_bindings(body_expr, &inner_scope, warnings);
+    }
+}
+
+/// The persistent storage a contract defines, as reported by
+///  `ContractAST::storage_footprint`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StorageFootprint {
+    pub maps: BTreeSet<ClarityName>,
+    pub data_vars: BTreeSet<ClarityName>,
+    pub fungible_tokens: BTreeSet<ClarityName>,
+    pub non_fungible_tokens: BTreeSet<ClarityName>,
+}
+
+/// The column width `ContractAST::to_source` tries to keep lines within
+pub const SOURCE_LINE_WIDTH: usize = 80;
+
+fn write_expression_source(source: &mut String, expr: &SymbolicExpression, indent: usize) {
+    let flat = expr.to_string();
+    let list = match expr.expr {
+        SymbolicExpressionType::List(ref list) if indent + flat.len() > SOURCE_LINE_WIDTH => list,
+        _ => {
+            source.push_str(&flat);
+            return;
+        }
+    };
+    source.push('(');
+    // an atom at the head of the list stays on the first line, along with the first argument
+    let first_line_len = match list.get(0).map(|head| &head.expr) {
+        Some(SymbolicExpressionType::Atom(_)) => 2,
+        _ => 1,
+    };
+    let mut column = indent + 1;
+    for (ix, item) in list.iter().enumerate() {
+        if ix >= first_line_len {
+            source.push('\n');
+            source.push_str(&" ".repeat(indent + 2));
+            column = indent + 2;
+        } else if ix > 0 {
+            source.push(' ');
+            column += 1;
+        }
+        let start = source.len();
+        write_expression_source(source, item, column);
+        column += source.len() - start;
+    }
+    source.push(')');
+}
+
+/// A reason a contract does not conform to a trait, as reported by
+///  `ContractAST::check_implements_trait`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraitConformanceError {
+    /// The trait method is not defined as a public or read-only function.
+    MissingMethod(ClarityName),
+    /// The method is defined, but its arguments do not match the trait's
+    ///  signature. The span is that of the offending define form.
+    SignatureMismatch(ClarityName, Span),
 }
 
 pub struct PreExpressionsDrain {
@@ -165,8 +165,10 @@ pub trait TransactionConnection: ClarityConnection {
         identifier: &QualifiedContractIdentifier,
         contract_content: &str,
     ) -> Result<(ContractAST, ContractAnalysis), Error> {
+        let epoch = self.get_epoch();
         self.with_analysis_db(|db, mut cost_track| {
-            let ast_result = ast::build_ast(identifier, contract_content, &mut cost_track);
+            let ast_result =
+                ast::build_ast_in_epoch(identifier, contract_content, &mut cost_track, epoch);
 
             let mut contract_ast = match ast_result {
                 Ok(x) => x,
@@ -179,6 +181,7 @@ pub trait TransactionConnection: ClarityConnection {
                 db,
                 false,
                 cost_track,
+                epoch,
             );
 
             match result {
@@ -862,7 +862,9 @@ impl<'a, 'b> Environment<'a, 'b> {
         contract_identifier: &QualifiedContractIdentifier,
         program: &str,
     ) -> Result<Value> {
-        let parsed = ast::build_ast(contract_identifier, program, self)?.expressions;
+        let epoch = *self.epoch();
+        let parsed =
+            ast::build_ast_in_epoch(contract_identifier, program, self, epoch)?.expressions;
 
         if parsed.len() < 1 {
             return Err(RuntimeErrorType::ParseError(
@@ -898,7 +900,8 @@ impl<'a, 'b> Environment<'a, 'b> {
     pub fn eval_raw(&mut self, program: &str) -> Result<Value> {
         let contract_id = QualifiedContractIdentifier::transient();
 
-        let parsed = ast::build_ast(&contract_id, program, self)?.expressions;
+        let epoch = *self.epoch();
+        let parsed = ast::build_ast_in_epoch(&contract_id, program, self, epoch)?.expressions;
         if parsed.len() < 1 {
             return Err(RuntimeErrorType::ParseError(
                 "Expected a program of at least length 1".to_string(),
@@ -1062,7 +1065,9 @@ impl<'a, 'b> Environment<'a, 'b> {
         contract_identifier: QualifiedContractIdentifier,
         contract_content: &str,
     ) -> Result<()> {
-        let contract_ast = ast::build_ast(&contract_identifier, contract_content, self)?;
+        let epoch = *self.epoch();
+        let contract_ast =
+            ast::build_ast_in_epoch(&contract_identifier, contract_content, self, epoch)?;
         self.initialize_contract_from_ast(contract_identifier, &contract_ast, &contract_content)
     }
 
@@ -678,7 +678,7 @@ impl LimitedCostTracker {
                 panic!("Attempted to get default cost functions for Epoch 1.0 where Clarity does not exist");
             }
             StacksEpochId::Epoch20 => COSTS_1_NAME.to_string(),
-            StacksEpochId::Epoch2_05 => COSTS_2_NAME.to_string(),
+            StacksEpochId::Epoch2_05 | StacksEpochId::Epoch21 => COSTS_2_NAME.to_string(),
         }
     }
 }
@@ -22,6 +22,7 @@ use std::fmt;
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum Level {
     Error,
+    Warning,
 }
 
 pub trait DiagnosableError {
@@ -47,6 +48,15 @@ impl Diagnostic {
         }
     }
 
+    pub fn warning(message: String) -> Diagnostic {
+        Diagnostic {
+            spans: vec![],
+            level: Level::Warning,
+            message,
+            suggestion: None,
+        }
+    }
+
     pub fn add_span(&mut self, start_line: u32, start_column: u32, end_line: u32, end_column: u32) {
         self.spans.push(Span {
             start_line,
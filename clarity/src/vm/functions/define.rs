@@ -14,13 +14,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::types::StacksEpochId;
 use crate::vm::callables::{DefineType, DefinedFunction};
 use crate::vm::contexts::{ContractContext, Environment, LocalContext};
 use crate::vm::errors::{
-    check_argument_count, check_arguments_at_least, CheckErrors, InterpreterResult as Result,
-    RuntimeErrorType,
+    check_argument_count, check_arguments_at_least, CheckErrors, Error,
+    InterpreterResult as Result, RuntimeErrorType,
 };
-use crate::vm::eval;
+use crate::vm::functions::NativeFunctions;
 use crate::vm::representations::SymbolicExpressionType::{
     Atom, AtomValue, Field, List, LiteralValue,
 };
@@ -30,7 +31,8 @@ use crate::vm::types::{
     parse_name_type_pairs, PrincipalData, QualifiedContractIdentifier, TraitIdentifier,
     TupleTypeSignature, TypeSignature, Value,
 };
-use std::collections::{BTreeMap, HashMap};
+use crate::vm::{eval, is_reserved};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 define_named_enum!(DefineFunctions {
     Constant("define-constant"),
@@ -97,12 +99,31 @@ pub enum DefineFunctionsParsed<'a> {
     },
 }
 
+/// The supply of a token defined with `define-fungible-token`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenSupply {
+    /// no total supply was given, so any amount may be minted
+    Unlimited,
+    /// at most this many tokens may ever be minted
+    Capped(u128),
+}
+
+impl TokenSupply {
+    /// The maximum total supply, or `None` if the supply is unlimited
+    pub fn cap(&self) -> Option<u128> {
+        match self {
+            TokenSupply::Unlimited => None,
+            TokenSupply::Capped(total_supply) => Some(*total_supply),
+        }
+    }
+}
+
 pub enum DefineResult {
     Variable(ClarityName, Value),
     Function(ClarityName, DefinedFunction),
     Map(ClarityName, TypeSignature, TypeSignature),
     PersistedVariable(ClarityName, TypeSignature, Value),
-    FungibleToken(ClarityName, Option<u128>),
+    FungibleToken(ClarityName, TokenSupply),
     NonFungibleAsset(ClarityName, TypeSignature),
     Trait(ClarityName, BTreeMap<ClarityName, FunctionSignature>),
     UseTrait(ClarityName, TraitIdentifier),
@@ -110,8 +131,29 @@ pub enum DefineResult {
     NoDefine,
 }
 
-fn check_legal_define(name: &str, contract_context: &ContractContext) -> Result<()> {
-    if contract_context.is_name_used(name) {
+impl DefineResult {
+    /// For a map definition, the most bytes that one of its keys and one of its values can take
+    ///  up once serialized, e.g. for estimating the map's storage costs.  `None` for any other
+    ///  kind of define.
+    pub fn map_max_serialized_sizes(&self) -> Result<Option<(u32, u32)>> {
+        match self {
+            DefineResult::Map(_, key_type, value_type) => Ok(Some((
+                key_type.max_serialized_size()?,
+                value_type.max_serialized_size()?,
+            ))),
+            _ => Ok(None),
+        }
+    }
+}
+
+fn check_legal_define(
+    name: &str,
+    contract_context: &ContractContext,
+    epoch: &StacksEpochId,
+) -> Result<()> {
+    if *epoch >= StacksEpochId::Epoch21 && is_reserved(name) {
+        Err(CheckErrors::ReservedName(name.to_string()).into())
+    } else if contract_context.is_name_used(name) {
         Err(CheckErrors::NameAlreadyUsed(name.to_string()).into())
     } else {
         Ok(())
@@ -124,7 +166,7 @@ fn handle_define_variable(
     env: &mut Environment,
 ) -> Result<DefineResult> {
     // is the variable name legal?
-    check_legal_define(variable, &env.contract_context)?;
+    check_legal_define(variable, &env.contract_context, env.epoch())?;
     let context = LocalContext::new();
     let value = eval(expression, env, &context)?;
     Ok(DefineResult::Variable(variable.clone(), value))
@@ -144,12 +186,22 @@ fn handle_define_function(
         .match_atom()
         .ok_or(CheckErrors::ExpectedName)?;
 
-    check_legal_define(&function_name, &env.contract_context)?;
+    check_legal_define(&function_name, &env.contract_context, env.epoch())?;
 
     let arguments = parse_name_type_pairs(arg_symbols, env)?;
 
+    let mut argument_names = HashSet::new();
     for (argument, _) in arguments.iter() {
-        check_legal_define(argument, &env.contract_context)?;
+        check_legal_define(argument, &env.contract_context, env.epoch())?;
+        if *env.epoch() >= StacksEpochId::Epoch21 && !argument_names.insert(argument) {
+            return Err(CheckErrors::NameAlreadyUsed(argument.to_string()).into());
+        }
+    }
+
+    if *env.epoch() >= StacksEpochId::Epoch21 && define_type == DefineType::Public {
+        if let Some(returns) = literal_non_response_return(expression) {
+            return Err(CheckErrors::PublicFunctionMustReturnResponse(returns).into());
+        }
     }
 
     let function = DefinedFunction::new(
@@ -163,13 +215,33 @@ fn handle_define_function(
     Ok(DefineResult::Function(function_name.clone(), function))
 }
 
+/// If the value returned by a function body is obviously not a response -- i.e., it is a
+///  literal, possibly at the end of a `begin` -- return the literal's type.  Anything else is
+///  left for the type checker to decide.
+fn literal_non_response_return(body: &SymbolicExpression) -> Option<TypeSignature> {
+    match body.expr {
+        AtomValue(ref value) | LiteralValue(ref value) => match value {
+            Value::Response(_) => None,
+            _ => Some(TypeSignature::type_of(value)),
+        },
+        List(ref exprs) => {
+            let (function, args) = exprs.split_first()?;
+            match NativeFunctions::lookup_by_name(function.match_atom()?) {
+                Some(NativeFunctions::Begin) => literal_non_response_return(args.last()?),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
 fn handle_define_persisted_variable(
     variable_str: &ClarityName,
     value_type: &SymbolicExpression,
     value: &SymbolicExpression,
     env: &mut Environment,
 ) -> Result<DefineResult> {
-    check_legal_define(&variable_str, &env.contract_context)?;
+    check_legal_define(&variable_str, &env.contract_context, env.epoch())?;
 
     let value_type_signature = TypeSignature::parse_type_repr(value_type, env)?;
 
@@ -188,9 +260,12 @@ fn handle_define_nonfungible_asset(
     key_type: &SymbolicExpression,
     env: &mut Environment,
 ) -> Result<DefineResult> {
-    check_legal_define(&asset_name, &env.contract_context)?;
+    check_legal_define(&asset_name, &env.contract_context, env.epoch())?;
 
     let key_type_signature = TypeSignature::parse_type_repr(key_type, env)?;
+    if *env.epoch() >= StacksEpochId::Epoch21 && !key_type_signature.is_valid_nft_key_type() {
+        return Err(CheckErrors::DefineNFTBadKeyType(key_type_signature).into());
+    }
 
     Ok(DefineResult::NonFungibleAsset(
         asset_name.clone(),
@@ -203,7 +278,7 @@ fn handle_define_fungible_token(
     total_supply: Option<&SymbolicExpression>,
     env: &mut Environment,
 ) -> Result<DefineResult> {
-    check_legal_define(&asset_name, &env.contract_context)?;
+    check_legal_define(&asset_name, &env.contract_context, env.epoch())?;
 
     if let Some(total_supply_expr) = total_supply {
         let context = LocalContext::new();
@@ -211,13 +286,16 @@ fn handle_define_fungible_token(
         if let Value::UInt(total_supply_int) = total_supply_value {
             Ok(DefineResult::FungibleToken(
                 asset_name.clone(),
-                Some(total_supply_int),
+                TokenSupply::Capped(total_supply_int),
             ))
         } else {
             Err(CheckErrors::TypeValueError(TypeSignature::UIntType, total_supply_value).into())
         }
     } else {
-        Ok(DefineResult::FungibleToken(asset_name.clone(), None))
+        Ok(DefineResult::FungibleToken(
+            asset_name.clone(),
+            TokenSupply::Unlimited,
+        ))
     }
 }
 
@@ -227,7 +305,7 @@ fn handle_define_map(
     value_type: &SymbolicExpression,
     env: &mut Environment,
 ) -> Result<DefineResult> {
-    check_legal_define(&map_str, &env.contract_context)?;
+    check_legal_define(&map_str, &env.contract_context, env.epoch())?;
 
     let key_type_signature = TypeSignature::parse_type_repr(key_type, env)?;
     let value_type_signature = TypeSignature::parse_type_repr(value_type, env)?;
@@ -244,7 +322,7 @@ fn handle_define_trait(
     functions: &[SymbolicExpression],
     env: &mut Environment,
 ) -> Result<DefineResult> {
-    check_legal_define(&name, &env.contract_context)?;
+    check_legal_define(&name, &env.contract_context, env.epoch())?;
 
     let trait_signature = TypeSignature::parse_trait_type_repr(&functions, env)?;
 
@@ -266,6 +344,18 @@ fn handle_impl_trait(trait_identifier: &TraitIdentifier) -> Result<DefineResult>
 }
 
 impl DefineFunctions {
+    /// Every define keyword, in declaration order. Useful for tooling which
+    ///  wants to enumerate the top-level forms (e.g., editor autocompletion).
+    pub fn all() -> &'static [DefineFunctions] {
+        DefineFunctions::ALL
+    }
+
+    /// The keyword strings of every define form (e.g., `define-public`), in
+    ///  the same order as `DefineFunctions::all()`.
+    pub fn keywords() -> &'static [&'static str] {
+        DefineFunctions::ALL_NAMES
+    }
+
     pub fn try_parse(
         expression: &SymbolicExpression,
     ) -> Option<(DefineFunctions, &[SymbolicExpression])> {
@@ -450,3 +540,74 @@ pub fn evaluate_define(
         Ok(DefineResult::NoDefine)
     }
 }
+
+/// Evaluate the define of each of `expressions` (e.g. a contract's top-level expressions),
+///  without executing the rest of the contract.  As in `eval_all`, the defines are evaluated
+///  in order, and each successful result is bound into a scratch copy of the contract context,
+///  so later defines can refer to earlier constants and functions.  Each define runs in a
+///  read-only context that is rolled back, so nothing is written to the database (and
+///  storage defines only reserve their names).  Returns the results of the defines, in order,
+///  and the errors of any defines that failed.  Expressions that are not defines are skipped.
+pub fn evaluate_all_defines(
+    expressions: &[SymbolicExpression],
+    env: &mut Environment,
+) -> (Vec<DefineResult>, Vec<Error>) {
+    let mut scratch_context = env.contract_context.clone();
+    let mut results = vec![];
+    let mut errors = vec![];
+    for expression in expressions.iter() {
+        env.global_context.begin_read_only();
+        let result = {
+            let mut scratch_env = Environment::new(
+                env.global_context,
+                &scratch_context,
+                env.call_stack,
+                env.sender.clone(),
+                env.caller.clone(),
+            );
+            evaluate_define(expression, &mut scratch_env)
+        };
+        env.global_context.roll_back();
+        match result {
+            Ok(DefineResult::NoDefine) => {}
+            Ok(define) => {
+                bind_define_result(&define, &mut scratch_context);
+                results.push(define);
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+    (results, errors)
+}
+
+fn bind_define_result(define: &DefineResult, contract_context: &mut ContractContext) {
+    match define {
+        DefineResult::Variable(name, value) => {
+            contract_context
+                .variables
+                .insert(name.clone(), value.clone());
+        }
+        DefineResult::Function(name, function) => {
+            contract_context
+                .functions
+                .insert(name.clone(), function.clone());
+        }
+        DefineResult::Map(name, ..)
+        | DefineResult::PersistedVariable(name, ..)
+        | DefineResult::FungibleToken(name, _)
+        | DefineResult::NonFungibleAsset(name, _) => {
+            contract_context.persisted_names.insert(name.clone());
+        }
+        DefineResult::Trait(name, trait_type) => {
+            contract_context
+                .defined_traits
+                .insert(name.clone(), trait_type.clone());
+        }
+        DefineResult::ImplTrait(trait_identifier) => {
+            contract_context
+                .implemented_traits
+                .insert(trait_identifier.clone());
+        }
+        DefineResult::UseTrait(..) | DefineResult::NoDefine => {}
+    }
+}
@@ -50,7 +50,9 @@ macro_rules! switch_on_global_epoch {
                     panic!("Executing Clarity method during Epoch 1.0, before Clarity")
                 }
                 StacksEpochId::Epoch20 => $Epoch2Version(args, env, context),
-                StacksEpochId::Epoch2_05 => $Epoch205Version(args, env, context),
+                StacksEpochId::Epoch2_05 | StacksEpochId::Epoch21 => {
+                    $Epoch205Version(args, env, context)
+                }
             }
         }
     };
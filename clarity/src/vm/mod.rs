@@ -341,7 +341,7 @@ pub fn eval_all(
                     global_context.add_memory(TypeSignature::UIntType.type_size()
                                               .expect("type size should be realizable") as u64)?;
 
-                    let data_type = global_context.database.create_fungible_token(&contract_context.contract_identifier, &name, &total_supply);
+                    let data_type = global_context.database.create_fungible_token(&contract_context.contract_identifier, &name, &total_supply.cap());
 
                     contract_context.meta_ft.insert(name, data_type);
                 },
@@ -420,7 +420,7 @@ pub fn execute_in_epoch(
     let mut global_context =
         GlobalContext::new(use_mainnet, conn, LimitedCostTracker::new_free(), epoch);
     global_context.execute(|g| {
-        let parsed = ast::build_ast(&contract_id, program, &mut ())?.expressions;
+        let parsed = ast::build_ast_in_epoch(&contract_id, program, &mut (), epoch)?.expressions;
         eval_all(&parsed, &mut contract_context, g)
     })
 }
@@ -15,7 +15,9 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::vm::errors::RuntimeErrorType;
-use crate::vm::types::{QualifiedContractIdentifier, TraitIdentifier, Value};
+use crate::vm::types::{
+    CharType, QualifiedContractIdentifier, SequenceData, TraitIdentifier, Value,
+};
 use regex::Regex;
 use stacks_common::codec::Error as codec_error;
 use stacks_common::codec::{read_next, read_next_at_most, write_next, StacksMessageCodec};
@@ -153,6 +155,10 @@ pub struct PreSymbolicExpression {
 
     #[cfg(feature = "developer-mode")]
     pub span: Span,
+
+    #[cfg(feature = "developer-mode")]
+    #[serde(default)]
+    pub byte_span: ByteSpan,
 }
 
 pub trait SymbolicExpressionCommon {
@@ -195,6 +201,7 @@ impl PreSymbolicExpression {
         PreSymbolicExpression {
             id: 0,
             span: Span::zero(),
+            byte_span: ByteSpan::zero(),
             pre_expr: PreSymbolicExpressionType::AtomValue(Value::Bool(false)),
         }
     }
@@ -226,6 +233,31 @@ impl PreSymbolicExpression {
     ) {
     }
 
+    #[cfg(feature = "developer-mode")]
+    pub fn set_byte_span(&mut self, start: u32, end: u32) {
+        self.byte_span = ByteSpan { start, end }
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn set_byte_span(&mut self, _start: u32, _end: u32) {}
+
+    /// The byte offsets in the source of this expression's first and one-past-last characters
+    #[cfg(feature = "developer-mode")]
+    pub fn byte_span(&self) -> ByteSpan {
+        self.byte_span.clone()
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn byte_span(&self) -> ByteSpan {
+        ByteSpan::zero()
+    }
+
+    /// Move this expression's payload out, leaving a placeholder behind.  The id and spans
+    ///  stay with the expression.
+    pub fn take_pre_expr(&mut self) -> PreSymbolicExpressionType {
+        std::mem::replace(&mut self.pre_expr, PreSymbolicExpression::cons().pre_expr)
+    }
+
     pub fn sugared_contract_identifier(val: ContractName) -> PreSymbolicExpression {
         PreSymbolicExpression {
             pre_expr: PreSymbolicExpressionType::SugaredContractIdentifier(val),
@@ -374,6 +406,10 @@ pub struct SymbolicExpression {
 
     #[cfg(feature = "developer-mode")]
     pub span: Span,
+
+    #[cfg(feature = "developer-mode")]
+    #[serde(default)]
+    pub byte_span: ByteSpan,
 }
 
 impl SymbolicExpression {
@@ -382,6 +418,7 @@ impl SymbolicExpression {
         SymbolicExpression {
             id: 0,
             span: Span::zero(),
+            byte_span: ByteSpan::zero(),
             expr: SymbolicExpressionType::AtomValue(Value::Bool(false)),
         }
     }
@@ -413,6 +450,50 @@ impl SymbolicExpression {
     ) {
     }
 
+    #[cfg(feature = "developer-mode")]
+    pub fn set_byte_span(&mut self, start: u32, end: u32) {
+        self.byte_span = ByteSpan { start, end }
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn set_byte_span(&mut self, _start: u32, _end: u32) {}
+
+    /// The byte offsets in the source of this expression's first and one-past-last characters
+    #[cfg(feature = "developer-mode")]
+    pub fn byte_span(&self) -> ByteSpan {
+        self.byte_span.clone()
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn byte_span(&self) -> ByteSpan {
+        ByteSpan::zero()
+    }
+
+    /// The line/column range in the source of this expression
+    #[cfg(feature = "developer-mode")]
+    pub fn span(&self) -> Span {
+        self.span.clone()
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn span(&self) -> Span {
+        Span::zero()
+    }
+
+    /// Give this expression the same source span as `other`, the pre-expression
+    ///  it was expanded from, so that errors point at the original source.
+    #[cfg(feature = "developer-mode")]
+    pub fn with_span_of(mut self, other: &PreSymbolicExpression) -> SymbolicExpression {
+        self.span = other.span.clone();
+        self.byte_span = other.byte_span.clone();
+        self
+    }
+
+    #[cfg(not(feature = "developer-mode"))]
+    pub fn with_span_of(self, _other: &PreSymbolicExpression) -> SymbolicExpression {
+        self
+    }
+
     pub fn atom_value(val: Value) -> SymbolicExpression {
         SymbolicExpression {
             expr: SymbolicExpressionType::AtomValue(val),
@@ -513,27 +594,32 @@ impl SymbolicExpression {
 }
 
 impl fmt::Display for SymbolicExpression {
+    /// Renders the expression as Clarity source on a single line.  Values are written the way
+    /// the parser reads them, so the output parses back into the same expression.
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.expr {
             SymbolicExpressionType::List(ref list) => {
                 write!(f, "(")?;
-                for item in list.iter() {
-                    write!(f, " {}", item)?;
+                for (ix, item) in list.iter().enumerate() {
+                    if ix > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
                 }
-                write!(f, " )")?;
+                write!(f, ")")?;
             }
             SymbolicExpressionType::Atom(ref value) => {
                 write!(f, "{}", &**value)?;
             }
             SymbolicExpressionType::AtomValue(ref value)
             | SymbolicExpressionType::LiteralValue(ref value) => {
-                write!(f, "{}", value)?;
+                write_source_value(f, value)?;
             }
             SymbolicExpressionType::TraitReference(ref value, _) => {
                 write!(f, "<{}>", &**value)?;
             }
             SymbolicExpressionType::Field(ref value) => {
-                write!(f, "<{}>", value)?;
+                write!(f, "'{}", value)?;
             }
         };
 
@@ -541,6 +627,47 @@ impl fmt::Display for SymbolicExpression {
     }
 }
 
+/// Write a literal value as Clarity source.  This differs from `Value`'s `Display` in that
+/// principals are quoted, and strings only use the escapes the parser understands.
+fn write_source_value(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match value {
+        Value::Principal(principal) => write!(f, "'{}", principal),
+        Value::Sequence(SequenceData::String(CharType::ASCII(string))) => {
+            write!(f, "\"")?;
+            for c in string.data.iter() {
+                write_source_ascii_char(f, *c)?;
+            }
+            write!(f, "\"")
+        }
+        Value::Sequence(SequenceData::String(CharType::UTF8(string))) => {
+            write!(f, "u\"")?;
+            for c in string.data.iter() {
+                match std::str::from_utf8(c).ok().and_then(|c| c.chars().next()) {
+                    Some(unicode_char) if c.len() > 1 => {
+                        write!(f, "\\u{{{:x}}}", unicode_char as u32)?
+                    }
+                    _ => write_source_ascii_char(f, c[0])?,
+                }
+            }
+            write!(f, "\"")
+        }
+        _ => write!(f, "{}", value),
+    }
+}
+
+fn write_source_ascii_char(f: &mut fmt::Formatter, c: u8) -> fmt::Result {
+    match c {
+        b'\\' => write!(f, "\\\\"),
+        b'"' => write!(f, "\\\""),
+        b'\n' => write!(f, "\\n"),
+        b'\t' => write!(f, "\\t"),
+        b'\r' => write!(f, "\\r"),
+        b'\0' => write!(f, "\\0"),
+        b' '..=b'~' => write!(f, "{}", c as char),
+        _ => write!(f, "\\x{:02x}", c),
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash)]
 pub struct Span {
     pub start_line: u32,
@@ -559,3 +686,18 @@ impl Span {
         }
     }
 }
+
+/// A half-open range [start, end) of byte offsets into the parsed source, for tools that edit
+///  source text directly.  Offsets index the original source, including any leading byte order
+///  mark that the parser stripped.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Default)]
+pub struct ByteSpan {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl ByteSpan {
+    pub fn zero() -> ByteSpan {
+        ByteSpan { start: 0, end: 0 }
+    }
+}
@@ -14,11 +14,18 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::types::StacksEpochId;
 use crate::vm::ast::build_ast;
 use crate::vm::ast::errors::ParseErrors;
+use crate::vm::contexts::Environment;
 use crate::vm::errors::{CheckErrors, Error, RuntimeErrorType};
-use crate::vm::execute;
-use crate::vm::types::{QualifiedContractIdentifier, TypeSignature, Value};
+use crate::vm::functions::define::{
+    evaluate_all_defines, evaluate_define, DefineFunctions, DefineResult, TokenSupply,
+};
+use crate::vm::representations::SymbolicExpression;
+use crate::vm::tests::with_memory_environment;
+use crate::vm::types::{QualifiedContractIdentifier, TupleData, TypeSignature, Value};
+use crate::vm::{execute, execute_in_epoch};
 
 fn assert_eq_err(e1: CheckErrors, e2: Error) {
     let e1: Error = e1.into();
@@ -103,6 +110,89 @@ fn test_bad_define_names() {
     );
 }
 
+#[test]
+fn test_reserved_define_names() {
+    let execute_21 = |program| execute_in_epoch(program, StacksEpochId::Epoch21, false);
+
+    assert_eq_err(
+        CheckErrors::ReservedName("block-height".to_string()),
+        execute_21("(define-constant block-height u1)").unwrap_err(),
+    );
+    assert_eq_err(
+        CheckErrors::ReservedName("true".to_string()),
+        execute_21("(define-data-var true bool false)").unwrap_err(),
+    );
+    assert_eq_err(
+        CheckErrors::ReservedName("block-height".to_string()),
+        execute_21("(define-private (foo (block-height uint)) block-height)").unwrap_err(),
+    );
+    assert_eq_err(
+        CheckErrors::NameAlreadyUsed("height".to_string()),
+        execute_21("(define-constant height u1) (define-map height uint uint)").unwrap_err(),
+    );
+
+    // before 2.1, a reserved name is reported as already used
+    assert_eq_err(
+        CheckErrors::NameAlreadyUsed("block-height".to_string()),
+        execute("(define-constant block-height u1)").unwrap_err(),
+    );
+}
+
+#[test]
+fn test_define_nft_key_types() {
+    let execute_21 = |program| execute_in_epoch(program, StacksEpochId::Epoch21, false);
+
+    execute_21("(define-non-fungible-token names uint)").unwrap();
+    execute_21("(define-non-fungible-token names { owner: principal, serial: (buff 8) })").unwrap();
+
+    assert_eq_err(
+        CheckErrors::DefineNFTBadKeyType(
+            TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::IntType).unwrap(),
+        ),
+        execute_21("(define-non-fungible-token names (response uint int))").unwrap_err(),
+    );
+    assert_eq_err(
+        CheckErrors::DefineNFTBadKeyType(
+            TypeSignature::new_option(
+                TypeSignature::new_response(TypeSignature::UIntType, TypeSignature::IntType)
+                    .unwrap(),
+            )
+            .unwrap(),
+        ),
+        execute_21("(define-non-fungible-token names (optional (response uint int)))").unwrap_err(),
+    );
+
+    // before 2.1, any identifier type is accepted
+    assert_eq!(
+        Ok(None),
+        execute("(define-non-fungible-token names (response uint int))")
+    );
+}
+
+#[test]
+fn test_duplicate_argument_names() {
+    let execute_21 = |program| execute_in_epoch(program, StacksEpochId::Epoch21, false);
+
+    assert_eq_err(
+        CheckErrors::NameAlreadyUsed("x".to_string()),
+        execute_21("(define-private (f (x int) (y int) (x int)) x)").unwrap_err(),
+    );
+    assert_eq!(
+        Ok(Some(Value::Int(3))),
+        execute_21("(define-private (f (x int) (y int)) (+ x y)) (f 1 2)")
+    );
+
+    // before 2.1, the define succeeds and only calling the function fails
+    assert_eq!(
+        Ok(None),
+        execute("(define-private (f (x int) (y int) (x int)) x)")
+    );
+    assert_eq_err(
+        CheckErrors::NameAlreadyUsed("x".to_string()),
+        execute("(define-private (f (x int) (y int) (x int)) x) (f 1 2 3)").unwrap_err(),
+    );
+}
+
 #[test]
 fn test_unwrap_ret() {
     let test0 = "(define-private (foo) (unwrap! (ok 1) 2)) (foo)";
@@ -266,3 +356,172 @@ fn test_define_parse_panic_2() {
         execute(&tests).unwrap_err(),
     );
 }
+
+#[test]
+fn test_define_functions_listing() {
+    let all = DefineFunctions::all();
+    assert_eq!(all.len(), 11);
+    assert_eq!(all.len(), DefineFunctions::keywords().len());
+
+    for (define, keyword) in all.iter().zip(DefineFunctions::keywords().iter()) {
+        assert_eq!(define.get_name_str(), *keyword);
+        assert_eq!(DefineFunctions::lookup_by_name(keyword), Some(*define));
+    }
+
+    assert!(DefineFunctions::keywords().contains(&"define-constant"));
+    assert!(DefineFunctions::keywords().contains(&"define-public"));
+    assert_eq!(DefineFunctions::lookup_by_name("define-everything"), None);
+}
+
+#[test]
+fn test_define_public_literal_return() {
+    let execute_21 = |program| execute_in_epoch(program, StacksEpochId::Epoch21, false);
+
+    execute_21("(define-public (foo) (ok true)) (foo)").unwrap();
+    execute_21("(define-public (foo) (begin (print 1) (err u1))) (foo)").unwrap();
+
+    assert_eq_err(
+        CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::IntType),
+        execute_21("(define-public (foo) 1)").unwrap_err(),
+    );
+    assert_eq_err(
+        CheckErrors::PublicFunctionMustReturnResponse(TypeSignature::UIntType),
+        execute_21("(define-public (foo) (begin (print 1) u1))").unwrap_err(),
+    );
+
+    // only public functions must return a response
+    execute_21("(define-private (foo) 1) (foo)").unwrap();
+
+    // before 2.1, the define succeeds
+    assert_eq!(Ok(None), execute("(define-public (foo) 1)"));
+}
+
+/// Build `contract` and run `f` over its expressions in a fresh memory environment.
+fn with_defines_environment<F, R>(contract: &str, f: F) -> R
+where
+    F: FnOnce(&[SymbolicExpression], &mut Environment) -> R,
+{
+    let expressions = build_ast(&QualifiedContractIdentifier::transient(), contract, &mut ())
+        .unwrap()
+        .expressions;
+    let mut result = None;
+    with_memory_environment(
+        |owned_env| {
+            let mut env = owned_env.get_exec_environment(None);
+            result = Some(f(&expressions, &mut env));
+        },
+        false,
+    );
+    result.unwrap()
+}
+
+/// Evaluate the define in the first expression of `contract`.
+fn eval_first_define(contract: &str) -> Result<DefineResult, Error> {
+    with_defines_environment(contract, |expressions, env| {
+        evaluate_define(&expressions[0], env)
+    })
+}
+
+#[test]
+fn test_define_map_max_serialized_sizes() {
+    let define =
+        eval_first_define("(define-map owners { id: uint, serial: (buff 8) } principal)").unwrap();
+    let key = Value::from(
+        TupleData::from_data(vec![
+            ("id".into(), Value::UInt(u128::max_value())),
+            ("serial".into(), Value::buff_from(vec![0xff; 8]).unwrap()),
+        ])
+        .unwrap(),
+    );
+    let (key_size, value_size) = define.map_max_serialized_sizes().unwrap().unwrap();
+    assert_eq!(key_size, key.serialized_size());
+    assert_eq!(
+        value_size,
+        TypeSignature::PrincipalType.max_serialized_size().unwrap()
+    );
+
+    let define = eval_first_define("(define-data-var total uint u0)").unwrap();
+    assert_eq!(define.map_max_serialized_sizes().unwrap(), None);
+}
+
+fn eval_define_ft(contract: &str) -> Result<TokenSupply, Error> {
+    match eval_first_define(contract)? {
+        DefineResult::FungibleToken(name, total_supply) => {
+            assert_eq!(name.as_str(), "stackaroos");
+            Ok(total_supply)
+        }
+        _ => panic!("Expected a fungible token definition"),
+    }
+}
+
+#[test]
+fn test_define_ft_supply() {
+    assert_eq!(
+        eval_define_ft("(define-fungible-token stackaroos)").unwrap(),
+        TokenSupply::Unlimited
+    );
+    assert_eq!(
+        eval_define_ft("(define-fungible-token stackaroos u100)").unwrap(),
+        TokenSupply::Capped(100)
+    );
+    assert_eq!(TokenSupply::Unlimited.cap(), None);
+    assert_eq!(TokenSupply::Capped(100).cap(), Some(100));
+}
+
+#[test]
+fn test_evaluate_all_defines() {
+    let contract = "(define-constant c u1)
+        (define-map balances principal uint)
+        (print c)
+        (define-public (get-c) (ok c))
+        (define-constant d (+ c u1))
+        (define-constant e (* d u2))
+        (define-constant f (unwrap-panic (get-c)))
+        (define-data-var balances uint u0)
+        (define-constant g undefined-constant)
+        (define-fungible-token stackaroos u100)";
+    let (results, errors) = with_defines_environment(contract, |expressions, env| {
+        let outcome = evaluate_all_defines(expressions, env);
+        // nothing is bound into the environment's own contract context
+        assert!(env.contract_context.variables.is_empty());
+        assert!(env.contract_context.functions.is_empty());
+        outcome
+    });
+
+    let names: Vec<_> = results
+        .iter()
+        .map(|result| match result {
+            DefineResult::Variable(name, value) => {
+                let expected = match name.as_str() {
+                    "c" | "f" => 1,
+                    "d" => 2,
+                    "e" => 4,
+                    _ => panic!("Unexpected constant"),
+                };
+                assert_eq!(value, &Value::UInt(expected));
+                name.to_string()
+            }
+            DefineResult::Map(name, _, _) => name.to_string(),
+            DefineResult::Function(name, _) => name.to_string(),
+            DefineResult::FungibleToken(name, total_supply) => {
+                assert_eq!(total_supply, &TokenSupply::Capped(100));
+                name.to_string()
+            }
+            _ => panic!("Unexpected define result"),
+        })
+        .collect();
+    assert_eq!(
+        names,
+        vec!["c", "balances", "get-c", "d", "e", "f", "stackaroos"]
+    );
+
+    assert_eq!(errors.len(), 2);
+    assert_eq!(
+        errors[0],
+        CheckErrors::NameAlreadyUsed("balances".to_string()).into()
+    );
+    assert_eq!(
+        errors[1],
+        CheckErrors::UndefinedVariable("undefined-constant".to_string()).into()
+    );
+}
@@ -37,10 +37,10 @@ use crate::vm::representations::{
 };
 
 pub use crate::vm::types::signatures::{
-    parse_name_type_pairs, AssetIdentifier, BufferLength, FixedFunction, FunctionArg,
-    FunctionSignature, FunctionType, ListTypeData, SequenceSubtype, StringSubtype,
-    StringUTF8Length, TupleTypeSignature, TypeSignature, BUFF_1, BUFF_20, BUFF_32, BUFF_33,
-    BUFF_64, BUFF_65,
+    parse_name_type_pairs, parse_name_type_pairs_located, AssetIdentifier, BufferLength,
+    FixedFunction, FunctionArg, FunctionSignature, FunctionType, ListTypeData, SequenceSubtype,
+    StringSubtype, StringUTF8Length, TupleTypeSignature, TypeSignature, BUFF_1, BUFF_20, BUFF_32,
+    BUFF_33, BUFF_64, BUFF_65,
 };
 
 pub const MAX_VALUE_SIZE: u32 = 1024 * 1024; // 1MB
@@ -20,6 +20,8 @@ use std::convert::{TryFrom, TryInto};
 use std::hash::{Hash, Hasher};
 use std::{cmp, fmt};
 
+use crate::vm::analysis::errors::CheckError;
+use crate::vm::ast::parser::CONTRACT_MAX_NAME_LENGTH;
 use crate::vm::costs::{cost_functions, runtime_cost, CostOverflowingMath};
 use crate::vm::errors::{CheckErrors, Error as VMError, IncomparableError, RuntimeErrorType};
 use crate::vm::representations::{
@@ -397,6 +399,65 @@ impl TypeSignature {
         &TypeSignature::NoType == self
     }
 
+    /// Can this type be used as a non-fungible token's identifier type?  Identifiers must be
+    ///  plain, comparable data: responses (and types containing them), trait references and
+    ///  the empty type are not allowed.
+    pub fn is_valid_nft_key_type(&self) -> bool {
+        match self {
+            IntType | UIntType | BoolType | PrincipalType => true,
+            SequenceType(SequenceSubtype::BufferType(_))
+            | SequenceType(SequenceSubtype::StringType(_)) => true,
+            SequenceType(SequenceSubtype::ListType(list_data)) => {
+                list_data.get_list_item_type().is_valid_nft_key_type()
+            }
+            TupleType(tuple_sig) => tuple_sig
+                .get_type_map()
+                .values()
+                .all(|field_type| field_type.is_valid_nft_key_type()),
+            OptionalType(inner_type) => inner_type.is_valid_nft_key_type(),
+            NoType | ResponseType(_) | TraitReferenceType(_) => false,
+        }
+    }
+
+    /// The most bytes that a value of this type can take up once serialized, e.g. as a data map
+    ///  key or value.  The empty type and trait references are never serialized, so they have
+    ///  no such bound.
+    pub fn max_serialized_size(&self) -> Result<u32> {
+        // every serialized value starts with a 1-byte type prefix
+        let payload_size = match self {
+            NoType | TraitReferenceType(_) => {
+                return Err(CheckErrors::NoSerializedSizeBound(self.clone()))
+            }
+            IntType | UIntType => Some(16),
+            BoolType => Some(0),
+            // the larger of the two principal encodings:
+            //  issuer version + hash bytes + contract name length + contract name
+            PrincipalType => Some(1 + 20 + 1 + CONTRACT_MAX_NAME_LENGTH as u32),
+            SequenceType(SequenceSubtype::BufferType(len))
+            | SequenceType(SequenceSubtype::StringType(StringSubtype::ASCII(len))) => {
+                u32::from(len).checked_add(4)
+            }
+            // each UTF-8 character is encoded in at most 4 bytes
+            SequenceType(SequenceSubtype::StringType(StringSubtype::UTF8(len))) => {
+                u32::from(len).checked_mul(4).and_then(|x| x.checked_add(4))
+            }
+            SequenceType(SequenceSubtype::ListType(list_data)) => list_data
+                .get_list_item_type()
+                .max_serialized_size()?
+                .checked_mul(list_data.get_max_len())
+                .and_then(|x| x.checked_add(4)),
+            OptionalType(inner_type) => Some(inner_type.max_serialized_size()?),
+            ResponseType(response_types) => Some(cmp::max(
+                response_types.0.max_serialized_size()?,
+                response_types.1.max_serialized_size()?,
+            )),
+            TupleType(tuple_sig) => return tuple_sig.max_serialized_size(),
+        };
+        payload_size
+            .and_then(|x| x.checked_add(1))
+            .ok_or(CheckErrors::ValueTooLarge)
+    }
+
     pub fn admits(&self, x: &Value) -> bool {
         let x_type = TypeSignature::type_of(x);
         self.admits_type(&x_type)
@@ -1003,52 +1064,78 @@ impl TypeSignature {
         type_args: &[SymbolicExpression],
         accounting: &mut A,
     ) -> Result<BTreeMap<ClarityName, FunctionSignature>> {
+        TypeSignature::parse_trait_type_repr_located(type_args, accounting).map_err(|e| e.err)
+    }
+
+    /// Like `parse_trait_type_repr`, but a malformed method signature's error carries the span
+    /// of that method's `(name (arg-types ...) return-type)` expression.
+    pub fn parse_trait_type_repr_located<A: CostTracker>(
+        type_args: &[SymbolicExpression],
+        accounting: &mut A,
+    ) -> std::result::Result<BTreeMap<ClarityName, FunctionSignature>, CheckError> {
         let mut trait_signature: BTreeMap<ClarityName, FunctionSignature> = BTreeMap::new();
         let functions_types = type_args[0]
             .match_list()
             .ok_or(CheckErrors::DefineTraitBadSignature)?;
 
         for function_type in functions_types.iter() {
-            let args = function_type
-                .match_list()
-                .ok_or(CheckErrors::DefineTraitBadSignature)?;
-            if args.len() != 3 {
-                return Err(CheckErrors::InvalidTypeDescription);
-            }
+            let (fn_name, fn_signature) =
+                TypeSignature::parse_trait_method_repr(function_type, accounting).map_err(
+                    |err| {
+                        let mut located = CheckError::new(err);
+                        located.set_expression(function_type);
+                        located
+                    },
+                )?;
+            trait_signature.insert(fn_name, fn_signature);
+        }
+        Ok(trait_signature)
+    }
 
-            // Extract function's name
-            let fn_name = args[0]
-                .match_atom()
-                .ok_or(CheckErrors::DefineTraitBadSignature)?;
-
-            // Extract function's arguments
-            let fn_args_exprs = args[1]
-                .match_list()
-                .ok_or(CheckErrors::DefineTraitBadSignature)?;
-            let mut fn_args = vec![];
-            for arg_type in fn_args_exprs.iter() {
-                let arg_t = TypeSignature::parse_type_repr(&arg_type, accounting)?;
-                fn_args.push(arg_t);
-            }
+    // Parses a trait method signature of the form:
+    // (method-name (arg-type-0 arg-type-1 ...) (response ok-type err-type))
+    fn parse_trait_method_repr<A: CostTracker>(
+        function_type: &SymbolicExpression,
+        accounting: &mut A,
+    ) -> Result<(ClarityName, FunctionSignature)> {
+        let args = function_type
+            .match_list()
+            .ok_or(CheckErrors::DefineTraitBadSignature)?;
+        if args.len() != 3 {
+            return Err(CheckErrors::InvalidTypeDescription);
+        }
 
-            // Extract function's type return - must be a response
-            let fn_return = match TypeSignature::parse_type_repr(&args[2], accounting) {
-                Ok(response) => match response {
-                    TypeSignature::ResponseType(_) => Ok(response),
-                    _ => Err(CheckErrors::DefineTraitBadSignature),
-                },
-                _ => Err(CheckErrors::DefineTraitBadSignature),
-            }?;
+        // Extract function's name
+        let fn_name = args[0]
+            .match_atom()
+            .ok_or(CheckErrors::DefineTraitBadSignature)?;
 
-            trait_signature.insert(
-                fn_name.clone(),
-                FunctionSignature {
-                    args: fn_args,
-                    returns: fn_return,
-                },
-            );
+        // Extract function's arguments
+        let fn_args_exprs = args[1]
+            .match_list()
+            .ok_or(CheckErrors::DefineTraitBadSignature)?;
+        let mut fn_args = vec![];
+        for arg_type in fn_args_exprs.iter() {
+            let arg_t = TypeSignature::parse_type_repr(&arg_type, accounting)?;
+            fn_args.push(arg_t);
         }
-        Ok(trait_signature)
+
+        // Extract function's type return - must be a response
+        let fn_return = match TypeSignature::parse_type_repr(&args[2], accounting) {
+            Ok(response) => match response {
+                TypeSignature::ResponseType(_) => Ok(response),
+                _ => Err(CheckErrors::DefineTraitBadSignature),
+            },
+            _ => Err(CheckErrors::DefineTraitBadSignature),
+        }?;
+
+        Ok((
+            fn_name.clone(),
+            FunctionSignature {
+                args: fn_args,
+                returns: fn_return,
+            },
+        ))
     }
 }
 
@@ -1200,6 +1287,21 @@ impl TupleTypeSignature {
             .expect("size() overflowed on a constructed type.")
     }
 
+    /// The most bytes that a value of this tuple type can take up once serialized:
+    ///    1-byte type prefix + 4-byte field count + sum(1-byte name length + name + value)
+    pub fn max_serialized_size(&self) -> Result<u32> {
+        let mut total_size: u32 = 1 + 4;
+        for (name, type_signature) in self.type_map.iter() {
+            let field_size = type_signature.max_serialized_size()?;
+            // name.len() is bound to MAX_STRING_LEN (128), so `as u32` won't ever truncate
+            total_size = total_size
+                .checked_add(1 + name.len() as u32)
+                .and_then(|x| x.checked_add(field_size))
+                .ok_or(CheckErrors::ValueTooLarge)?;
+        }
+        Ok(total_size)
+    }
+
     fn max_depth(&self) -> u8 {
         let mut max = 0;
         for (_name, type_signature) in self.type_map.iter() {
@@ -1240,42 +1342,51 @@ pub fn parse_name_type_pairs<A: CostTracker>(
     name_type_pairs: &[SymbolicExpression],
     accounting: &mut A,
 ) -> Result<Vec<(ClarityName, TypeSignature)>> {
+    parse_name_type_pairs_located(name_type_pairs, accounting).map_err(|e| e.err)
+}
+
+/// Like `parse_name_type_pairs`, but a failure carries the span of the offending
+/// `(name type)` pair, so that diagnostics can point at it.
+pub fn parse_name_type_pairs_located<A: CostTracker>(
+    name_type_pairs: &[SymbolicExpression],
+    accounting: &mut A,
+) -> std::result::Result<Vec<(ClarityName, TypeSignature)>, CheckError> {
     // this is a pretty deep nesting here, but what we're trying to do is pick out the values of
     // the form:
     // ((name1 type1) (name2 type2) (name3 type3) ...)
     // which is a list of 2-length lists of atoms.
-    use crate::vm::representations::SymbolicExpressionType::{Atom, List};
+    use crate::vm::representations::SymbolicExpressionType::List;
+
+    let located = |err: CheckErrors, key_type_pair: &SymbolicExpression| {
+        let mut located = CheckError::new(err);
+        located.set_expression(key_type_pair);
+        located
+    };
 
     // step 1: parse it into a vec of symbolicexpression pairs.
-    let as_pairs: Result<Vec<_>> = name_type_pairs
+    let as_pairs = name_type_pairs
         .iter()
-        .map(|key_type_pair| {
-            if let List(ref as_vec) = key_type_pair.expr {
-                if as_vec.len() != 2 {
-                    Err(CheckErrors::BadSyntaxExpectedListOfPairs)
-                } else {
-                    Ok((&as_vec[0], &as_vec[1]))
-                }
-            } else {
-                Err(CheckErrors::BadSyntaxExpectedListOfPairs)
-            }
+        .map(|key_type_pair| match key_type_pair.expr {
+            List(ref as_vec) if as_vec.len() == 2 => Ok((key_type_pair, &as_vec[0], &as_vec[1])),
+            _ => Err(located(
+                CheckErrors::BadSyntaxExpectedListOfPairs,
+                key_type_pair,
+            )),
         })
-        .collect();
+        .collect::<std::result::Result<Vec<_>, CheckError>>()?;
 
     // step 2: turn into a vec of (name, typesignature) pairs.
-    let key_types: Result<Vec<_>> = (as_pairs?)
-        .iter()
-        .map(|(name_symbol, type_symbol)| {
+    as_pairs
+        .into_iter()
+        .map(|(key_type_pair, name_symbol, type_symbol)| {
             let name = name_symbol
                 .match_atom()
-                .ok_or(CheckErrors::BadSyntaxExpectedListOfPairs)?
+                .ok_or_else(|| located(CheckErrors::BadSyntaxExpectedListOfPairs, key_type_pair))?
                 .clone();
             let type_info = TypeSignature::parse_type_repr(type_symbol, accounting)?;
             Ok((name, type_info))
         })
-        .collect();
-
-    key_types
+        .collect()
 }
 
 impl fmt::Display for TupleTypeSignature {
@@ -1395,6 +1506,58 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_max_serialized_size() {
+        let tuple_type = TupleTypeSignature::try_from(vec![
+            ("a".into(), TypeSignature::IntType),
+            ("bb".into(), TypeSignature::from("(buff 10)")),
+            (
+                "c".into(),
+                TypeSignature::from("(optional (response bool uint))"),
+            ),
+        ])
+        .unwrap();
+        // the largest value of this type is exactly as large as the bound
+        let largest = execute("{a: 1, bb: 0x00000000000000000000, c: (some (err u1))}")
+            .unwrap()
+            .unwrap();
+        assert!(TypeSignature::TupleType(tuple_type.clone()).admits(&largest));
+        assert_eq!(
+            tuple_type.max_serialized_size().unwrap(),
+            largest.serialized_size()
+        );
+        assert_eq!(
+            TypeSignature::TupleType(tuple_type).max_serialized_size(),
+            Ok(largest.serialized_size())
+        );
+
+        // contract principals are the larger principal encoding
+        let principal = execute(&format!(
+            "'S1G2081040G2081040G2081040G208105NK8PE5.{}",
+            "a".repeat(CONTRACT_MAX_NAME_LENGTH)
+        ))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            TypeSignature::PrincipalType.max_serialized_size(),
+            Ok(principal.serialized_size())
+        );
+        assert_eq!(
+            TypeSignature::from("(list 3 (string-utf8 2))").max_serialized_size(),
+            Ok(1 + 4 + 3 * (1 + 4 + 8))
+        );
+
+        let tuple_type = TupleTypeSignature::try_from(vec![
+            ("a".into(), TypeSignature::IntType),
+            ("b".into(), TypeSignature::NoType),
+        ])
+        .unwrap();
+        assert_eq!(
+            tuple_type.max_serialized_size(),
+            Err(NoSerializedSizeBound(TypeSignature::NoType))
+        );
+    }
+
     #[test]
     fn test_construction() {
         let bad_type_descriptions = [
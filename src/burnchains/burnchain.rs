@@ -33,7 +33,7 @@ use crate::burnchains::bitcoin::address::BitcoinAddress;
 use crate::burnchains::bitcoin::address::BitcoinAddressType;
 use crate::burnchains::bitcoin::BitcoinNetworkType;
 use crate::burnchains::bitcoin::{BitcoinInputType, BitcoinTxInput, BitcoinTxOutput};
-use crate::burnchains::db::BurnchainDB;
+use crate::burnchains::db::{BurnchainDB, DroppedTxReason};
 use crate::burnchains::indexer::{
     BurnBlockIPC, BurnHeaderIPC, BurnchainBlockDownloader, BurnchainBlockParser, BurnchainIndexer,
 };
@@ -685,10 +685,29 @@ impl Burnchain {
         burn_tx: &BurnchainTransaction,
         pre_stx_op_map: &HashMap<Txid, PreStxOp>,
     ) -> Option<BlockstackOperationType> {
+        Burnchain::classify_transaction_with_reason(
+            burnchain,
+            burnchain_db,
+            block_header,
+            burn_tx,
+            pre_stx_op_map,
+        )
+        .ok()
+    }
+
+    /// Like `classify_transaction`, but if the transaction is not a valid Blockstack operation,
+    ///   report why.
+    pub fn classify_transaction_with_reason(
+        burnchain: &Burnchain,
+        burnchain_db: &BurnchainDB,
+        block_header: &BurnchainBlockHeader,
+        burn_tx: &BurnchainTransaction,
+        pre_stx_op_map: &HashMap<Txid, PreStxOp>,
+    ) -> Result<BlockstackOperationType, DroppedTxReason> {
         match burn_tx.opcode() {
             x if x == Opcodes::LeaderKeyRegister as u8 => {
                 match LeaderKeyRegisterOp::from_tx(block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::LeaderKeyRegister(op)),
+                    Ok(op) => Ok(BlockstackOperationType::LeaderKeyRegister(op)),
                     Err(e) => {
                         warn!(
                             "Failed to parse leader key register tx";
@@ -696,13 +715,13 @@ impl Burnchain {
                             "data" => %to_hex(&burn_tx.data()),
                             "error" => ?e,
                         );
-                        None
+                        Err(DroppedTxReason::MalformedData)
                     }
                 }
             }
             x if x == Opcodes::LeaderBlockCommit as u8 => {
                 match LeaderBlockCommitOp::from_tx(burnchain, block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::LeaderBlockCommit(op)),
+                    Ok(op) => Ok(BlockstackOperationType::LeaderBlockCommit(op)),
                     Err(e) => {
                         warn!(
                             "Failed to parse leader block commit tx";
@@ -710,13 +729,13 @@ impl Burnchain {
                             "data" => %to_hex(&burn_tx.data()),
                             "error" => ?e,
                         );
-                        None
+                        Err(DroppedTxReason::MalformedData)
                     }
                 }
             }
             x if x == Opcodes::UserBurnSupport as u8 => {
                 match UserBurnSupportOp::from_tx(block_header, burn_tx) {
-                    Ok(op) => Some(BlockstackOperationType::UserBurnSupport(op)),
+                    Ok(op) => Ok(BlockstackOperationType::UserBurnSupport(op)),
                     Err(e) => {
                         warn!(
                             "Failed to parse user burn support tx";
@@ -724,13 +743,13 @@ impl Burnchain {
                             "data" => %to_hex(&burn_tx.data()),
                             "error" => ?e,
                         );
-                        None
+                        Err(DroppedTxReason::MalformedData)
                     }
                 }
             }
             x if x == Opcodes::PreStx as u8 => {
                 match PreStxOp::from_tx(block_header, burn_tx, burnchain.pox_constants.sunset_end) {
-                    Ok(op) => Some(BlockstackOperationType::PreStx(op)),
+                    Ok(op) => Ok(BlockstackOperationType::PreStx(op)),
                     Err(e) => {
                         warn!(
                             "Failed to parse pre stack stx tx";
@@ -738,12 +757,13 @@ impl Burnchain {
                             "data" => %to_hex(&burn_tx.data()),
                             "error" => ?e,
                         );
-                        None
+                        Err(DroppedTxReason::MalformedData)
                     }
                 }
             }
             x if x == Opcodes::TransferStx as u8 => {
-                let pre_stx_txid = TransferStxOp::get_sender_txid(burn_tx).ok()?;
+                let pre_stx_txid = TransferStxOp::get_sender_txid(burn_tx)
+                    .map_err(|_| DroppedTxReason::MalformedData)?;
                 let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
                     Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
                     None => burnchain_db.get_burnchain_op(pre_stx_txid),
@@ -751,7 +771,7 @@ impl Burnchain {
                 if let Some(BlockstackOperationType::PreStx(pre_stx)) = pre_stx_tx {
                     let sender = &pre_stx.output;
                     match TransferStxOp::from_tx(block_header, burn_tx, sender) {
-                        Ok(op) => Some(BlockstackOperationType::TransferStx(op)),
+                        Ok(op) => Ok(BlockstackOperationType::TransferStx(op)),
                         Err(e) => {
                             warn!(
                                 "Failed to parse transfer stx tx";
@@ -759,7 +779,7 @@ impl Burnchain {
                                 "data" => %to_hex(&burn_tx.data()),
                                 "error" => ?e,
                             );
-                            None
+                            Err(DroppedTxReason::MalformedData)
                         }
                     }
                 } else {
@@ -768,11 +788,12 @@ impl Burnchain {
                         "txid" => %burn_tx.txid(),
                         "pre_stx_txid" => %pre_stx_txid
                     );
-                    None
+                    Err(DroppedTxReason::MissingPreStx)
                 }
             }
             x if x == Opcodes::StackStx as u8 => {
-                let pre_stx_txid = StackStxOp::get_sender_txid(burn_tx).ok()?;
+                let pre_stx_txid = StackStxOp::get_sender_txid(burn_tx)
+                    .map_err(|_| DroppedTxReason::MalformedData)?;
                 let pre_stx_tx = match pre_stx_op_map.get(&pre_stx_txid) {
                     Some(tx_ref) => Some(BlockstackOperationType::PreStx(tx_ref.clone())),
                     None => burnchain_db.get_burnchain_op(pre_stx_txid),
@@ -785,7 +806,7 @@ impl Burnchain {
                         sender,
                         burnchain.pox_constants.sunset_end,
                     ) {
-                        Ok(op) => Some(BlockstackOperationType::StackStx(op)),
+                        Ok(op) => Ok(BlockstackOperationType::StackStx(op)),
                         Err(e) => {
                             warn!(
                                 "Failed to parse stack stx tx";
@@ -793,7 +814,7 @@ impl Burnchain {
                                 "data" => %to_hex(&burn_tx.data()),
                                 "error" => ?e,
                             );
-                            None
+                            Err(DroppedTxReason::MalformedData)
                         }
                     }
                 } else {
@@ -802,10 +823,10 @@ impl Burnchain {
                         "txid" => %burn_tx.txid().to_string(),
                         "pre_stx_txid" => %pre_stx_txid.to_string()
                     );
-                    None
+                    Err(DroppedTxReason::MissingPreStx)
                 }
             }
-            _ => None,
+            _ => Err(DroppedTxReason::NotBlockstackOp),
         }
     }
 
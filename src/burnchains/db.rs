@@ -14,32 +14,216 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use std::{fs, io};
 
 use rusqlite::{
-    types::ToSql, Connection, OpenFlags, OptionalExtension, Row, Transaction, NO_PARAMS,
+    backup::Backup, types::ToSql, Connection, Error as sqlite_error, ErrorCode, OpenFlags,
+    OptionalExtension, Row, Transaction, TransactionBehavior, NO_PARAMS,
 };
 use serde_json;
+use slog::{Drain, Logger};
 
 use crate::burnchains::Txid;
-use crate::burnchains::{Burnchain, BurnchainBlock, BurnchainBlockHeader, Error as BurnchainError};
-use crate::chainstate::burn::operations::BlockstackOperationType;
+use crate::burnchains::{
+    Burnchain, BurnchainBlock, BurnchainBlockHeader, BurnchainTransaction, Error as BurnchainError,
+};
+use crate::chainstate::burn::operations::{BlockstackOperationType, LeaderBlockCommitOp, PreStxOp};
 use crate::chainstate::stacks::index::MarfTrieId;
 use crate::util_lib::db::{
-    query_row, query_rows, sql_pragma, sqlite_open, tx_begin_immediate, tx_busy_handler,
+    query_count, query_int, query_row, query_rows, sql_pragma, sqlite_open, tx_busy_handler,
     u64_to_sql, Error as DBError, FromColumn, FromRow,
 };
 
 use crate::chainstate::stacks::index::ClarityMarfTrieId;
 use stacks_common::types::chainstate::BurnchainHeaderHash;
+use stacks_common::util::log;
+use stacks_common::util::sleep_ms;
 
 pub struct BurnchainDB {
     conn: Connection,
+    /// how many times to attempt a write transaction that hits a transient busy error.  Write
+    /// transactions only wait in `burnchain_db_busy_handler` when this is more than 1.
+    write_attempts: u32,
+    /// initial backoff between write transaction attempts, in milliseconds
+    write_retry_backoff_ms: u64,
+    /// if set, how long a transaction waits on a locked DB before failing with SQLITE_BUSY,
+    /// instead of waiting in a busy handler
+    busy_timeout: Option<Duration>,
+    /// if set, only the blockstack ops this accepts get stored
+    op_filter: Option<BlockstackOpFilter>,
+    /// turns each of a new block's transactions into a blockstack op
+    tx_classifier: Box<dyn BurnchainTxClassifier>,
+}
+
+/// Turns a burnchain transaction into the blockstack op it encodes.  BurnchainDB uses the
+/// bitcoin classifier by default; burnchains that encode ops differently can install their own
+/// with `BurnchainDB::set_tx_classifier()`.
+pub trait BurnchainTxClassifier: Send {
+    /// Classify `burn_tx`, or say why it is not a blockstack op.  `pre_stx_op_map` holds the
+    /// pre-stx ops found so far in the same block.
+    fn classify(
+        &self,
+        burnchain: &Burnchain,
+        burnchain_db: &BurnchainDB,
+        block_header: &BurnchainBlockHeader,
+        burn_tx: &BurnchainTransaction,
+        pre_stx_op_map: &HashMap<Txid, PreStxOp>,
+    ) -> Result<BlockstackOperationType, DroppedTxReason>;
+}
+
+/// The default transaction classifier, which decodes bitcoin-encoded blockstack ops
+pub struct BitcoinTxClassifier;
+
+impl BurnchainTxClassifier for BitcoinTxClassifier {
+    fn classify(
+        &self,
+        burnchain: &Burnchain,
+        burnchain_db: &BurnchainDB,
+        block_header: &BurnchainBlockHeader,
+        burn_tx: &BurnchainTransaction,
+        pre_stx_op_map: &HashMap<Txid, PreStxOp>,
+    ) -> Result<BlockstackOperationType, DroppedTxReason> {
+        Burnchain::classify_transaction_with_reason(
+            burnchain,
+            burnchain_db,
+            block_header,
+            burn_tx,
+            pre_stx_op_map,
+        )
+    }
+}
+
+/// A policy check run on each classified blockstack op before it is stored.  Returns whether or
+/// not to keep the op.
+pub type BlockstackOpFilter = Box<dyn Fn(&BlockstackOperationType) -> bool + Send>;
+
+/// Why a burnchain transaction was dropped instead of being stored as a blockstack op
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DroppedTxReason {
+    /// the transaction's opcode is not that of any blockstack op
+    NotBlockstackOp,
+    /// the transaction spends a pre-stx op that could not be found
+    MissingPreStx,
+    /// the transaction's data, inputs or outputs could not be parsed into its op
+    MalformedData,
+}
+
+/// A burnchain transaction that `store_new_burnchain_block_with_diagnostics()` dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DroppedTx {
+    pub txid: Txid,
+    pub reason: DroppedTxReason,
 }
 
 struct BurnchainDBTransaction<'a> {
     sql_tx: Transaction<'a>,
+    op_filter: Option<&'a (dyn Fn(&BlockstackOperationType) -> bool + Send)>,
+    /// logger for this transaction's writes.  Every record carries structured key/value fields
+    /// describing what was written.
+    log: Logger,
+}
+
+/// Make the logger used by BurnchainDB transactions: the global logger, filtered at the
+/// configured log level (since records sent to it bypass the level check in `debug!` et al.)
+fn make_burnchain_db_logger() -> Logger {
+    let drain = slog::LevelFilter::new(log::LOGGER.clone(), log::get_loglevel());
+    Logger::root(drain.ignore_res(), o!("component" => "burnchain_db"))
+}
+
+/// SQLite connection options applied when a BurnchainDB is connected or opened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BurnchainDBOpenOpts {
+    /// value of the `journal_mode` pragma (e.g. "WAL", "DELETE")
+    pub journal_mode: String,
+    /// value of the `synchronous` pragma (e.g. "OFF", "NORMAL", "FULL")
+    pub synchronous: String,
+    /// value of the `mmap_size` pragma, in bytes.  `None` keeps SQLite's default.
+    pub mmap_size: Option<u64>,
+    /// how many times to attempt a write transaction that fails with a transient
+    /// SQLITE_BUSY or SQLITE_LOCKED error before giving up.  The default of 1 keeps waiting on a
+    /// locked DB with the unbounded `tx_busy_handler`; with more than 1, write transactions
+    /// wait for at most `BURNCHAIN_DB_BUSY_HANDLER_MAX_RUNS` busy handler runs before retrying.
+    pub write_attempts: u32,
+    /// how long to wait before re-attempting such a write transaction, in milliseconds.  The
+    /// wait doubles with each subsequent attempt.
+    pub write_retry_backoff_ms: u64,
+    /// how long to wait on a DB locked by another connection before failing with a transient
+    /// SQLITE_BUSY error, in milliseconds.  `None` keeps the default of waiting in
+    /// `tx_busy_handler` (or, if write retries are enabled, `burnchain_db_busy_handler`).
+    pub busy_timeout_ms: Option<u64>,
+}
+
+impl Default for BurnchainDBOpenOpts {
+    fn default() -> BurnchainDBOpenOpts {
+        BurnchainDBOpenOpts {
+            journal_mode: "WAL".to_string(),
+            synchronous: "NORMAL".to_string(),
+            mmap_size: None,
+            write_attempts: 1,
+            write_retry_backoff_ms: 100,
+            busy_timeout_ms: None,
+        }
+    }
+}
+
+/// How to order burnchain headers of equal height when picking the canonical chain tip.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TipTiebreak {
+    /// The header with the lowest block hash wins (default behavior)
+    HashAsc,
+    /// The header with the highest block hash wins
+    HashDesc,
+}
+
+/// How many times `burnchain_db_busy_handler` waits on a locked DB before giving up
+const BURNCHAIN_DB_BUSY_HANDLER_MAX_RUNS: i32 = 20;
+
+/// Busy handler for BurnchainDB connections.  It backs off like `tx_busy_handler`, but gives up
+/// after `BURNCHAIN_DB_BUSY_HANDLER_MAX_RUNS` runs (a second or two), so that a write stuck
+/// behind another connection's lock fails with SQLITE_BUSY and gets retried from the top by
+/// `with_write_tx`.  Only installed for write transactions that opted into retries.
+fn burnchain_db_busy_handler(run_count: i32) -> bool {
+    if run_count >= BURNCHAIN_DB_BUSY_HANDLER_MAX_RUNS {
+        return false;
+    }
+    tx_busy_handler(run_count)
+}
+
+/// Is this a transient error that SQLite raises when another connection holds a lock we need?
+fn is_busy_error(e: &BurnchainError) -> bool {
+    match e {
+        BurnchainError::DBError(DBError::SqliteError(sqlite_error::SqliteFailure(err, _))) => {
+            err.code == ErrorCode::DatabaseBusy || err.code == ErrorCode::DatabaseLocked
+        }
+        _ => false,
+    }
+}
+
+/// Run `f` until it succeeds, fails with an error that is not a transient busy error, or has
+/// been attempted `max_attempts` times.  Waits `backoff_ms` before the first retry, and doubles
+/// the wait before each subsequent one.
+fn retry_on_busy<F, R>(max_attempts: u32, backoff_ms: u64, mut f: F) -> Result<R, BurnchainError>
+where
+    F: FnMut() -> Result<R, BurnchainError>,
+{
+    let mut attempt = 1;
+    loop {
+        match f() {
+            Err(e) if attempt < max_attempts && is_busy_error(&e) => {
+                let delay_ms = backoff_ms.saturating_mul(1 << (attempt - 1).min(16));
+                warn!("BurnchainDB write transaction is busy; retrying";
+                      "attempt" => attempt,
+                      "max_attempts" => max_attempts,
+                      "backoff_ms" => delay_ms,
+                      "err" => %e);
+                sleep_ms(delay_ms);
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
 }
 
 pub struct BurnchainBlockData {
@@ -110,7 +294,10 @@ impl FromRow<BlockstackOperationType> for BlockstackOperationType {
     }
 }
 
-pub const BURNCHAIN_DB_VERSION: &'static str = "1";
+pub const BURNCHAIN_DB_VERSION: &'static str = "3";
+
+/// How many pages to copy per step of an online backup
+const BURNCHAIN_DB_BACKUP_PAGES_PER_STEP: i32 = 128;
 
 const BURNCHAIN_DB_INITIAL_SCHEMA: &'static str = "
 CREATE TABLE burnchain_db_block_headers (
@@ -132,10 +319,26 @@ CREATE TABLE burnchain_db_block_ops (
 
 CREATE TABLE db_config(version TEXT NOT NULL);";
 
+/// Schema version 2 records each op's vtxindex, so ops can be looked up by position.
+/// Existing rows are backfilled from their serialized ops when migrating.
+const BURNCHAIN_DB_SCHEMA_2: &'static [&'static str] =
+    &["ALTER TABLE burnchain_db_block_ops ADD COLUMN vtxindex INTEGER NOT NULL DEFAULT 0;"];
+
+/// Schema version 3 records the UTXO spent by each block-commit, so commits spending the same
+/// input can be found.  These columns are NULL for other ops.  Existing block-commits are
+/// backfilled from their serialized ops when migrating.
+const BURNCHAIN_DB_SCHEMA_3: &'static [&'static str] = &[
+    "ALTER TABLE burnchain_db_block_ops ADD COLUMN input_txid TEXT;",
+    "ALTER TABLE burnchain_db_block_ops ADD COLUMN input_vout INTEGER;",
+];
+
 const BURNCHAIN_DB_INDEXES: &'static [&'static str] = &[
     "CREATE INDEX IF NOT EXISTS index_burnchain_db_block_headers_height_hash ON burnchain_db_block_headers(block_height DESC, block_hash ASC);",
     "CREATE INDEX IF NOT EXISTS index_burnchain_db_block_hash ON burnchain_db_block_ops(block_hash);",
     "CREATE INDEX IF NOT EXISTS index_burnchain_db_txid ON burnchain_db_block_ops(txid);",
+    "CREATE INDEX IF NOT EXISTS index_burnchain_db_block_hash_vtxindex ON burnchain_db_block_ops(block_hash, vtxindex);",
+    "CREATE INDEX IF NOT EXISTS index_burnchain_db_block_headers_parent_hash ON burnchain_db_block_headers(parent_block_hash);",
+    "CREATE INDEX IF NOT EXISTS index_burnchain_db_block_ops_input ON burnchain_db_block_ops(input_txid, input_vout);",
 ];
 
 impl<'a> BurnchainDBTransaction<'a> {
@@ -143,6 +346,12 @@ impl<'a> BurnchainDBTransaction<'a> {
         &self,
         header: &BurnchainBlockHeader,
     ) -> Result<i64, BurnchainError> {
+        slog_debug!(self.log, "Store burnchain block header";
+                    "block_height" => header.block_height,
+                    "burn_header_hash" => %header.block_hash,
+                    "parent_burn_header_hash" => %header.parent_block_hash,
+                    "num_txs" => header.num_txs);
+
         let sql = "INSERT INTO burnchain_db_block_headers
                    (block_height, block_hash, parent_block_hash, num_txs, timestamp)
                    VALUES (?, ?, ?, ?, ?)";
@@ -160,35 +369,281 @@ impl<'a> BurnchainDBTransaction<'a> {
         }
     }
 
+    /// Store the given ops, less any that the DB's op filter rejects.  Returns the ops stored.
     fn store_blockstack_ops(
         &self,
         block_hash: &BurnchainHeaderHash,
         block_ops: &[BlockstackOperationType],
-    ) -> Result<(), BurnchainError> {
+    ) -> Result<Vec<BlockstackOperationType>, BurnchainError> {
         let sql = "INSERT INTO burnchain_db_block_ops
-                   (block_hash, txid, op) VALUES (?, ?, ?)";
+                   (block_hash, txid, op, vtxindex, input_txid, input_vout)
+                   VALUES (?, ?, ?, ?, ?, ?)";
         let mut stmt = self.sql_tx.prepare(sql)?;
+        let mut stored_ops = Vec::with_capacity(block_ops.len());
         for op in block_ops.iter() {
+            if let Some(op_filter) = self.op_filter {
+                if !op_filter(op) {
+                    slog_debug!(self.log, "Drop blockstack op rejected by filter";
+                                "block_height" => op.block_height(),
+                                "burn_header_hash" => %block_hash,
+                                "txid" => %op.txid_ref(),
+                                "vtxindex" => op.vtxindex());
+                    continue;
+                }
+            }
+
+            slog_debug!(self.log, "Store blockstack op";
+                        "block_height" => op.block_height(),
+                        "burn_header_hash" => %block_hash,
+                        "txid" => %op.txid_ref(),
+                        "vtxindex" => op.vtxindex());
+
             let serialized_op =
                 serde_json::to_string(op).expect("Failed to serialize parsed BlockstackOp");
-            let args: &[&dyn ToSql] = &[block_hash, op.txid_ref(), &serialized_op];
+            let (input_txid, input_vout) = match op {
+                BlockstackOperationType::LeaderBlockCommit(commit) => {
+                    (Some(&commit.input.0), Some(commit.input.1))
+                }
+                _ => (None, None),
+            };
+            let args: &[&dyn ToSql] = &[
+                block_hash,
+                op.txid_ref(),
+                &serialized_op,
+                &op.vtxindex(),
+                &input_txid,
+                &input_vout,
+            ];
+            stmt.execute(args)?;
+            stored_ops.push(op.clone());
+        }
+        Ok(stored_ops)
+    }
+
+    /// Delete a burnchain block's header and ops, e.g. to roll it back after a reorg.  Only a
+    /// leaf block can be deleted: this fails if the block is the first block, or if any stored
+    /// header still names it as its parent.
+    fn delete_burnchain_block(
+        &self,
+        block_hash: &BurnchainHeaderHash,
+    ) -> Result<(), BurnchainError> {
+        let qry = "SELECT * FROM burnchain_db_block_headers WHERE block_hash = ?1";
+        let header: BurnchainBlockHeader = query_row(&self.sql_tx, qry, &[block_hash])?
+            .ok_or_else(|| BurnchainError::UnknownBlock(block_hash.clone()))?;
+        if header.parent_block_hash == BurnchainHeaderHash::sentinel() {
+            return Err(BurnchainError::BlockNotDeletable(block_hash.clone()));
+        }
+
+        let qry = "SELECT COUNT(*) FROM burnchain_db_block_headers WHERE parent_block_hash = ?1";
+        if query_int(&self.sql_tx, qry, &[block_hash])? > 0 {
+            return Err(BurnchainError::BlockNotDeletable(block_hash.clone()));
+        }
+
+        slog_debug!(self.log, "Delete burnchain block";
+                    "block_height" => header.block_height,
+                    "burn_header_hash" => %block_hash);
+        self.sql_tx.execute(
+            "DELETE FROM burnchain_db_block_ops WHERE block_hash = ?1",
+            &[block_hash],
+        )?;
+        self.sql_tx.execute(
+            "DELETE FROM burnchain_db_block_headers WHERE block_hash = ?1",
+            &[block_hash],
+        )?;
+        Ok(())
+    }
+
+    /// Delete the headers and ops of every stored burnchain block in the given reward cycle,
+    /// canonical or not, so that the cycle can be re-synced.  Only the latest stored reward
+    /// cycle can be purged, since purging an earlier one would orphan the headers after it.
+    /// The first block is never purged, since it precedes reward cycle 0's first block.
+    fn purge_reward_cycle(
+        &self,
+        burnchain: &Burnchain,
+        reward_cycle: u64,
+    ) -> Result<(), BurnchainError> {
+        let next_reward_cycle = reward_cycle.checked_add(1).ok_or(DBError::Overflow)?;
+        let start_height = burnchain.reward_cycle_to_block_height(reward_cycle);
+        let end_height = burnchain.reward_cycle_to_block_height(next_reward_cycle);
+
+        let qry = "SELECT COUNT(*) FROM burnchain_db_block_headers WHERE block_height >= ?1";
+        if query_int(&self.sql_tx, qry, &[&u64_to_sql(end_height)?])? > 0 {
+            return Err(BurnchainError::RewardCycleNotPurgeable(reward_cycle));
+        }
+
+        let args: &[&dyn ToSql] = &[&u64_to_sql(start_height)?, &u64_to_sql(end_height)?];
+
+        slog_debug!(self.log, "Purge reward cycle";
+                    "reward_cycle" => reward_cycle,
+                    "start_height" => start_height,
+                    "end_height" => end_height);
+        self.sql_tx.execute(
+            "DELETE FROM burnchain_db_block_ops WHERE block_hash IN
+             (SELECT block_hash FROM burnchain_db_block_headers
+              WHERE block_height >= ?1 AND block_height < ?2)",
+            args,
+        )?;
+        self.sql_tx.execute(
+            "DELETE FROM burnchain_db_block_headers WHERE block_height >= ?1 AND block_height < ?2",
+            args,
+        )?;
+        Ok(())
+    }
+
+    /// Add the vtxindex column to the ops table, and fill it in for the ops already stored
+    fn apply_schema_2(&self) -> Result<(), BurnchainError> {
+        for sql_exec in BURNCHAIN_DB_SCHEMA_2 {
+            self.sql_tx.execute_batch(sql_exec)?;
+        }
+
+        let stored_ops = {
+            let mut stmt = self
+                .sql_tx
+                .prepare("SELECT rowid, op FROM burnchain_db_block_ops")?;
+            let rows = stmt.query_map(NO_PARAMS, |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut stmt = self
+            .sql_tx
+            .prepare("UPDATE burnchain_db_block_ops SET vtxindex = ?1 WHERE rowid = ?2")?;
+        for (rowid, serialized_op) in stored_ops.into_iter() {
+            let op: BlockstackOperationType =
+                serde_json::from_str(&serialized_op).map_err(DBError::SerializationError)?;
+            let args: &[&dyn ToSql] = &[&op.vtxindex(), &rowid];
             stmt.execute(args)?;
         }
+
+        self.sql_tx
+            .execute("UPDATE db_config SET version = ?1", &["2"])?;
+        slog_info!(self.log, "Migrated burnchain DB schema"; "version" => "2");
+        Ok(())
+    }
+
+    /// Add the input columns to the ops table, and fill them in for the block-commits already
+    /// stored
+    fn apply_schema_3(&self) -> Result<(), BurnchainError> {
+        for sql_exec in BURNCHAIN_DB_SCHEMA_3 {
+            self.sql_tx.execute_batch(sql_exec)?;
+        }
+
+        // decode every op to find the block-commits, rather than relying on how serde encodes
+        // the op type
+        let stored_ops = {
+            let mut stmt = self
+                .sql_tx
+                .prepare("SELECT rowid, op FROM burnchain_db_block_ops")?;
+            let rows = stmt.query_map(NO_PARAMS, |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+
+        let mut stmt = self.sql_tx.prepare(
+            "UPDATE burnchain_db_block_ops SET input_txid = ?1, input_vout = ?2 WHERE rowid = ?3",
+        )?;
+        for (rowid, serialized_op) in stored_ops.into_iter() {
+            let op: BlockstackOperationType =
+                serde_json::from_str(&serialized_op).map_err(DBError::SerializationError)?;
+            if let BlockstackOperationType::LeaderBlockCommit(commit) = op {
+                let args: &[&dyn ToSql] = &[&commit.input.0, &commit.input.1, &rowid];
+                stmt.execute(args)?;
+            }
+        }
+
+        self.sql_tx
+            .execute("UPDATE db_config SET version = ?1", &["3"])?;
+        slog_info!(self.log, "Migrated burnchain DB schema"; "version" => "3");
         Ok(())
     }
 
     fn commit(self) -> Result<(), BurnchainError> {
-        self.sql_tx.commit().map_err(BurnchainError::from)
+        let log = self.log;
+        self.sql_tx.commit().map_err(|e| {
+            slog_warn!(log, "Failed to commit burnchain DB transaction"; "err" => %e);
+            BurnchainError::from(e)
+        })
     }
 }
 
 impl BurnchainDB {
+    fn from_conn(conn: Connection, opts: &BurnchainDBOpenOpts) -> BurnchainDB {
+        BurnchainDB {
+            conn,
+            write_attempts: opts.write_attempts,
+            write_retry_backoff_ms: opts.write_retry_backoff_ms,
+            busy_timeout: opts.busy_timeout_ms.map(Duration::from_millis),
+            op_filter: None,
+            tx_classifier: Box::new(BitcoinTxClassifier),
+        }
+    }
+
+    /// Get the database schema version, given a DB connection
+    fn get_schema_version(conn: &Connection) -> Result<Option<String>, DBError> {
+        let version = conn
+            .query_row("SELECT MAX(version) FROM db_config", NO_PARAMS, |row| {
+                row.get(0)
+            })
+            .optional()?;
+        Ok(version)
+    }
+
+    /// Fail with `OldSchema` if the DB is not at the latest schema version.  Used when the DB is
+    /// opened read-only and so cannot be migrated.
+    fn check_schema_version_or_error(&self) -> Result<(), BurnchainError> {
+        match BurnchainDB::get_schema_version(&self.conn)? {
+            Some(ref version) if version == BURNCHAIN_DB_VERSION => Ok(()),
+            Some(version) => {
+                let version_u64 = version.parse::<u64>().map_err(|_| DBError::Corruption)?;
+                Err(BurnchainError::from(DBError::OldSchema(version_u64)))
+            }
+            None => Err(BurnchainError::from(DBError::Corruption)),
+        }
+    }
+
+    /// Migrate the DB to the latest schema version
+    fn check_schema_version_and_update(&mut self) -> Result<(), BurnchainError> {
+        loop {
+            match BurnchainDB::get_schema_version(&self.conn)? {
+                Some(ref version) if version == "1" => {
+                    self.with_write_tx(|db_tx| db_tx.apply_schema_2())?;
+                }
+                Some(ref version) if version == "2" => {
+                    self.with_write_tx(|db_tx| db_tx.apply_schema_3())?;
+                }
+                Some(ref version) if version == BURNCHAIN_DB_VERSION => {
+                    return Ok(());
+                }
+                Some(version) => panic!(
+                    "The schema version of the burnchain DB is invalid: {}",
+                    version
+                ),
+                None => panic!("The schema version of the burnchain DB is not recorded."),
+            }
+        }
+    }
+
     fn add_indexes(&mut self) -> Result<(), BurnchainError> {
-        let db_tx = self.tx_begin()?;
-        for index in BURNCHAIN_DB_INDEXES.iter() {
-            db_tx.sql_tx.execute_batch(index)?;
+        self.with_write_tx(|db_tx| {
+            for index in BURNCHAIN_DB_INDEXES.iter() {
+                db_tx.sql_tx.execute_batch(index)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn apply_open_opts(conn: &Connection, opts: &BurnchainDBOpenOpts) -> Result<(), DBError> {
+        sql_pragma(conn, "journal_mode", &opts.journal_mode)?;
+        sql_pragma(conn, "synchronous", &opts.synchronous)?;
+        if let Some(mmap_size) = opts.mmap_size {
+            sql_pragma(conn, "mmap_size", &u64_to_sql(mmap_size)?)?;
+        }
+        match opts.busy_timeout_ms {
+            Some(busy_timeout_ms) => conn.busy_timeout(Duration::from_millis(busy_timeout_ms))?,
+            None => conn.busy_handler(Some(tx_busy_handler))?,
         }
-        db_tx.commit()?;
         Ok(())
     }
 
@@ -198,6 +653,26 @@ impl BurnchainDB {
         first_burn_header_hash: &BurnchainHeaderHash,
         first_burn_header_timestamp: u64,
         readwrite: bool,
+    ) -> Result<BurnchainDB, BurnchainError> {
+        BurnchainDB::connect_with_opts(
+            path,
+            first_block_height,
+            first_burn_header_hash,
+            first_burn_header_timestamp,
+            readwrite,
+            &BurnchainDBOpenOpts::default(),
+        )
+    }
+
+    /// Connect to (and if need be, instantiate) the burnchain DB, applying the given
+    /// SQLite connection options.
+    pub fn connect_with_opts(
+        path: &str,
+        first_block_height: u64,
+        first_burn_header_hash: &BurnchainHeaderHash,
+        first_burn_header_timestamp: u64,
+        readwrite: bool,
+        opts: &BurnchainDBOpenOpts,
     ) -> Result<BurnchainDB, BurnchainError> {
         let mut create_flag = false;
         let open_flags = match fs::metadata(path) {
@@ -225,11 +700,18 @@ impl BurnchainDB {
         };
 
         let conn = sqlite_open(path, open_flags, true)?;
-        let mut db = BurnchainDB { conn };
+        BurnchainDB::apply_open_opts(&conn, opts)?;
+        let mut db = BurnchainDB::from_conn(conn, opts);
 
         if create_flag {
             let db_tx = db.tx_begin()?;
             db_tx.sql_tx.execute_batch(BURNCHAIN_DB_INITIAL_SCHEMA)?;
+            for sql_exec in BURNCHAIN_DB_SCHEMA_2 {
+                db_tx.sql_tx.execute_batch(sql_exec)?;
+            }
+            for sql_exec in BURNCHAIN_DB_SCHEMA_3 {
+                db_tx.sql_tx.execute_batch(sql_exec)?;
+            }
 
             db_tx.sql_tx.execute(
                 "INSERT INTO db_config (version) VALUES (?1)",
@@ -249,33 +731,133 @@ impl BurnchainDB {
         }
 
         if readwrite {
+            db.check_schema_version_and_update()?;
             db.add_indexes()?;
+        } else {
+            db.check_schema_version_or_error()?;
         }
         Ok(db)
     }
 
     pub fn open(path: &str, readwrite: bool) -> Result<BurnchainDB, BurnchainError> {
+        BurnchainDB::open_with_opts(path, readwrite, &BurnchainDBOpenOpts::default())
+    }
+
+    /// Open an existing burnchain DB, applying the given SQLite connection options.
+    pub fn open_with_opts(
+        path: &str,
+        readwrite: bool,
+        opts: &BurnchainDBOpenOpts,
+    ) -> Result<BurnchainDB, BurnchainError> {
         let open_flags = if readwrite {
             OpenFlags::SQLITE_OPEN_READ_WRITE
         } else {
             OpenFlags::SQLITE_OPEN_READ_ONLY
         };
         let conn = sqlite_open(path, open_flags, true)?;
-        let mut db = BurnchainDB { conn };
+        BurnchainDB::apply_open_opts(&conn, opts)?;
+        let mut db = BurnchainDB::from_conn(conn, opts);
 
         if readwrite {
+            db.check_schema_version_and_update()?;
             db.add_indexes()?;
+        } else {
+            db.check_schema_version_or_error()?;
         }
         Ok(db)
     }
 
+    /// Copy this DB to a new file at `dest_path` using SQLite's online backup API, so a consistent
+    /// snapshot can be taken while the node keeps running.  Pages are copied a few at a time, and
+    /// whenever the live DB is busy or locked by a writer, the copy pauses for this DB's write
+    /// retry backoff and then resumes.  The copy is in WAL mode and can be opened with `open()`.
+    pub fn backup_to(&self, dest_path: &str) -> Result<(), BurnchainError> {
+        if fs::metadata(dest_path).is_ok() {
+            return Err(BurnchainError::FSError(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Backup destination {} already exists", dest_path),
+            )));
+        }
+
+        let mut dest_conn = sqlite_open(
+            dest_path,
+            OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+            true,
+        )
+        .map_err(DBError::SqliteError)?;
+        {
+            let backup = Backup::new(&self.conn, &mut dest_conn).map_err(DBError::SqliteError)?;
+            backup
+                .run_to_completion(
+                    BURNCHAIN_DB_BACKUP_PAGES_PER_STEP,
+                    Duration::from_millis(self.write_retry_backoff_ms),
+                    None,
+                )
+                .map_err(DBError::SqliteError)?;
+        }
+        // the copied header carries the source's journal mode; make sure the copy is in WAL mode
+        sql_pragma(&dest_conn, "journal_mode", &"WAL")?;
+        Ok(())
+    }
+
+    /// Begin a transaction, run `write` in it, and commit it.  If any of these steps fails with a
+    /// transient busy error (e.g. because another connection held the database lock for longer
+    /// than the busy handler was willing to wait), the transaction is rolled back and the whole
+    /// thing is retried, per this DB's write retry options.  Any other error is returned as-is.
+    fn with_write_tx<F, R>(&mut self, mut write: F) -> Result<R, BurnchainError>
+    where
+        F: FnMut(&BurnchainDBTransaction) -> Result<R, BurnchainError>,
+    {
+        let max_attempts = self.write_attempts;
+        let backoff_ms = self.write_retry_backoff_ms;
+        retry_on_busy(max_attempts, backoff_ms, || {
+            let db_tx = self.tx_begin()?;
+            let result = write(&db_tx)?;
+            db_tx.commit()?;
+            Ok(result)
+        })
+    }
+
     fn tx_begin<'a>(&'a mut self) -> Result<BurnchainDBTransaction<'a>, BurnchainError> {
-        let sql_tx = tx_begin_immediate(&mut self.conn)?;
-        Ok(BurnchainDBTransaction { sql_tx: sql_tx })
+        self.tx_begin_with_logger(make_burnchain_db_logger())
+    }
+
+    fn tx_begin_with_logger<'a>(
+        &'a mut self,
+        log: Logger,
+    ) -> Result<BurnchainDBTransaction<'a>, BurnchainError> {
+        let op_filter = self.op_filter.as_deref();
+        // under the unbounded tx_busy_handler, a busy write would never fail and so never get
+        // retried
+        match self.busy_timeout {
+            Some(busy_timeout) => self.conn.busy_timeout(busy_timeout)?,
+            None if self.write_attempts > 1 => {
+                self.conn.busy_handler(Some(burnchain_db_busy_handler))?
+            }
+            None => self.conn.busy_handler(Some(tx_busy_handler))?,
+        }
+        let sql_tx = Transaction::new(&mut self.conn, TransactionBehavior::Immediate)?;
+        Ok(BurnchainDBTransaction {
+            sql_tx: sql_tx,
+            op_filter: op_filter,
+            log: log,
+        })
     }
 
     pub fn get_canonical_chain_tip(&self) -> Result<BurnchainBlockHeader, BurnchainError> {
-        let qry = "SELECT * FROM burnchain_db_block_headers ORDER BY block_height DESC, block_hash ASC LIMIT 1";
+        self.get_canonical_chain_tip_with_tiebreak(TipTiebreak::HashAsc)
+    }
+
+    /// Get the highest burnchain header, using `tiebreak` to choose between headers at the same
+    /// height.  `get_canonical_chain_tip()` always uses `TipTiebreak::HashAsc`.
+    pub fn get_canonical_chain_tip_with_tiebreak(
+        &self,
+        tiebreak: TipTiebreak,
+    ) -> Result<BurnchainBlockHeader, BurnchainError> {
+        let qry = match tiebreak {
+            TipTiebreak::HashAsc => "SELECT * FROM burnchain_db_block_headers ORDER BY block_height DESC, block_hash ASC LIMIT 1",
+            TipTiebreak::HashDesc => "SELECT * FROM burnchain_db_block_headers ORDER BY block_height DESC, block_hash DESC LIMIT 1",
+        };
         let opt = query_row(&self.conn, qry, NO_PARAMS)?;
         opt.ok_or(BurnchainError::MissingParentBlock)
     }
@@ -298,6 +880,405 @@ impl BurnchainDB {
         })
     }
 
+    /// Get the block data of the given block's parent, or None if the given block is the first
+    /// block (whose parent is the sentinel hash).
+    pub fn get_parent_burnchain_block(
+        &self,
+        block: &BurnchainHeaderHash,
+    ) -> Result<Option<BurnchainBlockData>, BurnchainError> {
+        let header = self.get_burnchain_block(block)?.header;
+        if header.parent_block_hash == BurnchainHeaderHash::sentinel() {
+            return Ok(None);
+        }
+        self.get_burnchain_block(&header.parent_block_hash)
+            .map(Some)
+    }
+
+    /// Get the headers of every stored child of the given block, ordered by block hash.  More
+    /// than one child means the burnchain forked at that block.
+    pub fn get_children_headers(
+        &self,
+        parent_block_hash: &BurnchainHeaderHash,
+    ) -> Result<Vec<BurnchainBlockHeader>, DBError> {
+        let qry = "SELECT * FROM burnchain_db_block_headers
+                   WHERE parent_block_hash = ?1
+                   ORDER BY block_hash ASC";
+        query_rows(&self.conn, qry, &[parent_block_hash])
+    }
+
+    /// Check that the stored headers form an unbroken chain from the first block up to the
+    /// highest stored block.  Walking upward from the first block, return the first height
+    /// that either has no stored header, or has a header whose parent is not stored at the
+    /// height below it.  Returns None if there is no such height.
+    pub fn verify_header_contiguity(&self) -> Result<Option<u64>, DBError> {
+        let qry = "SELECT * FROM burnchain_db_block_headers ORDER BY block_height ASC";
+        let headers: Vec<BurnchainBlockHeader> = query_rows(&self.conn, qry, NO_PARAMS)?;
+        let mut headers = headers.into_iter().peekable();
+
+        let first_height = match headers.peek() {
+            Some(header) => header.block_height,
+            None => return Ok(None),
+        };
+        let mut prior_hashes = HashSet::new();
+        let mut height = first_height;
+        while let Some(next_height) = headers.peek().map(|header| header.block_height) {
+            if next_height != height {
+                // no header at this height
+                return Ok(Some(height));
+            }
+            let mut hashes = HashSet::new();
+            while let Some(header) = headers.next_if(|header| header.block_height == height) {
+                if height > first_height && !prior_hashes.contains(&header.parent_block_hash) {
+                    return Ok(Some(height));
+                }
+                hashes.insert(header.block_hash);
+            }
+            prior_hashes = hashes;
+            height = height.checked_add(1).ok_or(DBError::Overflow)?;
+        }
+        Ok(None)
+    }
+
+    /// Try to deserialize every stored op, e.g. to check a DB after upgrading.  Returns the
+    /// block hash, txid and deserialization error of each op that fails, in storage order.
+    /// Unlike loading ops via `FromRow`, this does not panic on an un-deserializable op.
+    pub fn validate_all_ops_deserialize(
+        &self,
+    ) -> Result<Vec<(BurnchainHeaderHash, Txid, String)>, DBError> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT block_hash, txid, op FROM burnchain_db_block_ops ORDER BY rowid")?;
+        let mut rows = stmt.query(NO_PARAMS)?;
+        let mut failures = vec![];
+        while let Some(row) = rows.next()? {
+            let serialized: String = row.get(2)?;
+            if let Err(e) = serde_json::from_str::<BlockstackOperationType>(&serialized) {
+                failures.push((row.get(0)?, row.get(1)?, e.to_string()));
+            }
+        }
+        Ok(failures)
+    }
+
+    /// Get the heights between the first block and the highest stored block (inclusive) that
+    /// have no stored header, in ascending order, e.g. to find which blocks to backfill.
+    pub fn missing_header_heights(&self) -> Result<Vec<u64>, DBError> {
+        let qry = "WITH RECURSIVE heights(height) AS (
+                       SELECT MIN(block_height) FROM burnchain_db_block_headers
+                       UNION ALL
+                       SELECT height + 1 FROM heights
+                       WHERE height < (SELECT MAX(block_height) FROM burnchain_db_block_headers)
+                   )
+                   SELECT height FROM heights
+                   WHERE height NOT IN (SELECT block_height FROM burnchain_db_block_headers)
+                   ORDER BY height ASC";
+        query_rows(&self.conn, qry, NO_PARAMS)
+    }
+
+    /// Get the headers on the canonical burnchain fork (i.e. the canonical chain tip and its
+    /// ancestors) with heights in [start_height, end_height), in ascending height order.
+    fn get_canonical_headers_in_range(
+        &self,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<Vec<BurnchainBlockHeader>, DBError> {
+        let tip = match self.get_canonical_chain_tip() {
+            Ok(tip) => tip,
+            Err(BurnchainError::DBError(e)) => return Err(e),
+            Err(_) => return Ok(vec![]),
+        };
+        let qry = "WITH RECURSIVE canonical(block_hash, parent_block_hash, block_height) AS (
+                       SELECT block_hash, parent_block_hash, block_height
+                       FROM burnchain_db_block_headers WHERE block_hash = ?1
+                       UNION ALL
+                       SELECT h.block_hash, h.parent_block_hash, h.block_height
+                       FROM burnchain_db_block_headers h JOIN canonical c ON h.block_hash = c.parent_block_hash
+                       WHERE c.block_height > ?2 AND h.block_height < c.block_height
+                   )
+                   SELECT h.* FROM burnchain_db_block_headers h JOIN canonical c ON h.block_hash = c.block_hash
+                   WHERE h.block_height >= ?2 AND h.block_height < ?3
+                   ORDER BY h.block_height ASC";
+        let args: &[&dyn ToSql] = &[
+            &tip.block_hash,
+            &u64_to_sql(start_height)?,
+            &u64_to_sql(end_height)?,
+        ];
+        query_rows(&self.conn, qry, args)
+    }
+
+    /// Get the blockstack ops stored for a burnchain block, ordered by vtxindex
+    fn get_blockstack_ops_in_block(
+        &self,
+        block_hash: &BurnchainHeaderHash,
+    ) -> Result<Vec<BlockstackOperationType>, DBError> {
+        let qry = "SELECT op FROM burnchain_db_block_ops WHERE block_hash = ? ORDER BY vtxindex";
+        query_rows(&self.conn, qry, &[block_hash])
+    }
+
+    /// Get the op stored at position `vtxindex` in the burnchain block `block_hash`, if any
+    pub fn get_burnchain_op_at(
+        &self,
+        block_hash: &BurnchainHeaderHash,
+        vtxindex: u32,
+    ) -> Result<Option<BlockstackOperationType>, DBError> {
+        let qry = "SELECT op FROM burnchain_db_block_ops WHERE block_hash = ?1 AND vtxindex = ?2";
+        let args: &[&dyn ToSql] = &[block_hash, &vtxindex];
+        query_row(&self.conn, qry, args)
+    }
+
+    /// Get every stored block-commit that spends the UTXO `(input_txid, input_vout)`, in any
+    /// fork, ordered by block height and then by vtxindex.  At most one of them can be valid in
+    /// a given fork.
+    pub fn get_commits_by_input(
+        &self,
+        input_txid: &Txid,
+        input_vout: u32,
+    ) -> Result<Vec<LeaderBlockCommitOp>, DBError> {
+        let qry = "SELECT ops.op FROM burnchain_db_block_ops ops
+                   JOIN burnchain_db_block_headers headers ON ops.block_hash = headers.block_hash
+                   WHERE ops.input_txid = ?1 AND ops.input_vout = ?2
+                   ORDER BY headers.block_height, ops.vtxindex";
+        let args: &[&dyn ToSql] = &[input_txid, &input_vout];
+        let ops: Vec<BlockstackOperationType> = query_rows(&self.conn, qry, args)?;
+        Ok(ops
+            .into_iter()
+            .filter_map(|op| match op {
+                BlockstackOperationType::LeaderBlockCommit(commit) => Some(commit),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Get the number of blockstack ops stored for the burnchain block `block_hash`, without
+    /// loading the ops themselves
+    pub fn get_op_count(&self, block_hash: &BurnchainHeaderHash) -> Result<u64, DBError> {
+        let qry = "SELECT COUNT(*) FROM burnchain_db_block_ops WHERE block_hash = ?1";
+        let count = query_count(&self.conn, qry, &[block_hash])?;
+        Ok(count as u64)
+    }
+
+    /// Replay the blockstack ops stored in the canonical burnchain fork with heights in
+    /// [start_height, end_height), in (block_height, vtxindex) order.  Blocks are loaded lazily,
+    /// one at a time, as the iterator advances.
+    pub fn iter_blockstack_ops<'a>(
+        &'a self,
+        start_height: u64,
+        end_height: u64,
+    ) -> impl Iterator<Item = Result<(u64, BlockstackOperationType), DBError>> + 'a {
+        let headers = match self.get_canonical_headers_in_range(start_height, end_height) {
+            Ok(headers) => headers.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        };
+        headers.into_iter().flat_map(move |header_res| {
+            let ops_res = header_res.and_then(|header| {
+                self.get_blockstack_ops_in_block(&header.block_hash)
+                    .map(|ops| (header.block_height, ops))
+            });
+            match ops_res {
+                Ok((block_height, ops)) => ops
+                    .into_iter()
+                    .map(|op| Ok((block_height, op)))
+                    .collect::<Vec<_>>(),
+                Err(e) => vec![Err(e)],
+            }
+        })
+    }
+
+    /// Get all block-commits in the canonical burnchain block at `block_height`, ordered by
+    /// vtxindex.  Choosing the sortition winner among them is up to the caller.
+    pub fn get_commits_at_height(
+        &self,
+        block_height: u64,
+    ) -> Result<Vec<LeaderBlockCommitOp>, DBError> {
+        let end_height = block_height.checked_add(1).ok_or(DBError::Overflow)?;
+        self.get_canonical_commits_in_range(block_height, end_height)
+    }
+
+    /// Get all block-commits in the prepare phase at the end of the given reward cycle (i.e. the
+    /// phase that chooses the anchor block for the next reward cycle), ordered by block height
+    /// and then by vtxindex.
+    pub fn get_prepare_phase_commits(
+        &self,
+        burnchain: &Burnchain,
+        reward_cycle: u64,
+    ) -> Result<Vec<LeaderBlockCommitOp>, DBError> {
+        let next_reward_cycle = reward_cycle.checked_add(1).ok_or(DBError::Overflow)?;
+        let end_height = burnchain.reward_cycle_to_block_height(next_reward_cycle);
+        let start_height = end_height
+            .checked_sub(burnchain.pox_constants.prepare_length as u64)
+            .ok_or(DBError::Overflow)?;
+        self.get_canonical_commits_in_range(start_height, end_height)
+    }
+
+    /// Get all block-commits in the canonical burnchain blocks with heights in
+    /// [start_height, end_height), ordered by block height and then by vtxindex.
+    fn get_canonical_commits_in_range(
+        &self,
+        start_height: u64,
+        end_height: u64,
+    ) -> Result<Vec<LeaderBlockCommitOp>, DBError> {
+        let mut commits = vec![];
+        for header in self.get_canonical_headers_in_range(start_height, end_height)? {
+            for op in self.get_blockstack_ops_in_block(&header.block_hash)? {
+                if let BlockstackOperationType::LeaderBlockCommit(commit) = op {
+                    commits.push(commit);
+                }
+            }
+        }
+        Ok(commits)
+    }
+
+    /// Get the total `burn_fee` of all block-commits in the canonical burnchain blocks of the
+    /// given reward cycle, i.e. the blocks from this cycle's first block up to (but excluding)
+    /// the next cycle's.  A commit's `sunset_burn` is not counted -- it is burnt in lieu of PoX
+    /// payouts during the sunset phase, and is not part of the commit's bid.
+    pub fn get_total_burn_in_reward_cycle(
+        &self,
+        burnchain: &Burnchain,
+        reward_cycle: u64,
+    ) -> Result<u64, DBError> {
+        let next_reward_cycle = reward_cycle.checked_add(1).ok_or(DBError::Overflow)?;
+        let start_height = burnchain.reward_cycle_to_block_height(reward_cycle);
+        let end_height = burnchain.reward_cycle_to_block_height(next_reward_cycle);
+
+        let mut total_burn: u64 = 0;
+        for header in self.get_canonical_headers_in_range(start_height, end_height)? {
+            for op in self.get_blockstack_ops_in_block(&header.block_hash)? {
+                if let BlockstackOperationType::LeaderBlockCommit(commit) = op {
+                    total_burn = total_burn
+                        .checked_add(commit.burn_fee)
+                        .ok_or(DBError::Overflow)?;
+                }
+            }
+        }
+        Ok(total_burn)
+    }
+
+    /// Get the median `burn_fee` of the block-commits in the canonical burnchain blocks of the
+    /// given reward cycle, or `None` if there are none.  With an even number of commits, this is
+    /// the lower of the two middle burn fees, so the result is always one of the commits' fees.
+    pub fn get_median_burn_in_reward_cycle(
+        &self,
+        burnchain: &Burnchain,
+        reward_cycle: u64,
+    ) -> Result<Option<u64>, DBError> {
+        let next_reward_cycle = reward_cycle.checked_add(1).ok_or(DBError::Overflow)?;
+        let start_height = burnchain.reward_cycle_to_block_height(reward_cycle);
+        let end_height = burnchain.reward_cycle_to_block_height(next_reward_cycle);
+
+        let mut burn_fees: Vec<u64> = self
+            .get_canonical_commits_in_range(start_height, end_height)?
+            .into_iter()
+            .map(|commit| commit.burn_fee)
+            .collect();
+        if burn_fees.is_empty() {
+            return Ok(None);
+        }
+        burn_fees.sort();
+        Ok(Some(burn_fees[(burn_fees.len() - 1) / 2]))
+    }
+
+    /// Count the distinct reward cycles spanned by `commit` and its ancestor block-commits,
+    /// following at most `max_depth` parent links.  Parents are looked up in the burnchain fork
+    /// containing `commit`.  The walk stops early at a commit with no parent, or whose parent
+    /// is not a stored block-commit at a lower height.
+    pub fn commit_chain_reward_cycle_span(
+        &self,
+        burnchain: &Burnchain,
+        commit: &LeaderBlockCommitOp,
+        max_depth: u64,
+    ) -> Result<u64, DBError> {
+        let mut reward_cycles = HashSet::new();
+        let mut block_hash = commit.burn_header_hash.clone();
+        let mut block_height = commit.block_height;
+        let mut parent_ptr = (commit.parent_block_ptr, commit.parent_vtxindex);
+        for depth in 0.. {
+            if let Some(reward_cycle) = burnchain.block_height_to_reward_cycle(block_height) {
+                reward_cycles.insert(reward_cycle);
+            }
+            let parent_height = parent_ptr.0 as u64;
+            if depth >= max_depth || parent_height == 0 || parent_height >= block_height {
+                break;
+            }
+            let parent_header = match self.get_ancestor_header(&block_hash, parent_height)? {
+                Some(header) => header,
+                None => break,
+            };
+            match self.get_burnchain_op_at(&parent_header.block_hash, parent_ptr.1 as u32)? {
+                Some(BlockstackOperationType::LeaderBlockCommit(parent)) => {
+                    block_hash = parent_header.block_hash;
+                    block_height = parent_header.block_height;
+                    parent_ptr = (parent.parent_block_ptr, parent.parent_vtxindex);
+                }
+                _ => break,
+            }
+        }
+        Ok(reward_cycles.len() as u64)
+    }
+
+    /// Get the header of the ancestor of `block_hash` (or the block itself) at `block_height`
+    fn get_ancestor_header(
+        &self,
+        block_hash: &BurnchainHeaderHash,
+        block_height: u64,
+    ) -> Result<Option<BurnchainBlockHeader>, DBError> {
+        let qry = "WITH RECURSIVE ancestors(block_hash, parent_block_hash, block_height) AS (
+                       SELECT block_hash, parent_block_hash, block_height
+                       FROM burnchain_db_block_headers WHERE block_hash = ?1
+                       UNION ALL
+                       SELECT h.block_hash, h.parent_block_hash, h.block_height
+                       FROM burnchain_db_block_headers h JOIN ancestors a ON h.block_hash = a.parent_block_hash
+                       WHERE a.block_height > ?2 AND h.block_height < a.block_height
+                   )
+                   SELECT h.* FROM burnchain_db_block_headers h JOIN ancestors a ON h.block_hash = a.block_hash
+                   WHERE h.block_height = ?2";
+        let args: &[&dyn ToSql] = &[block_hash, &u64_to_sql(block_height)?];
+        query_row(&self.conn, qry, args)
+    }
+
+    /// Get the heights in the given reward cycle that have at least one stored header, in any
+    /// fork, in ascending order.  A cycle is fully synced when this has one entry per block.
+    pub fn get_stored_heights_in_reward_cycle(
+        &self,
+        burnchain: &Burnchain,
+        reward_cycle: u64,
+    ) -> Result<Vec<u64>, DBError> {
+        let next_reward_cycle = reward_cycle.checked_add(1).ok_or(DBError::Overflow)?;
+        let start_height = burnchain.reward_cycle_to_block_height(reward_cycle);
+        let end_height = burnchain.reward_cycle_to_block_height(next_reward_cycle);
+        let qry = "SELECT DISTINCT block_height FROM burnchain_db_block_headers
+                   WHERE block_height >= ?1 AND block_height < ?2 ORDER BY block_height";
+        let args: &[&dyn ToSql] = &[&u64_to_sql(start_height)?, &u64_to_sql(end_height)?];
+        query_rows(&self.conn, qry, args)
+    }
+
+    /// Get the highest reward cycle whose blocks are all stored, i.e. whose last block is at or
+    /// below the canonical chain tip.  Returns None if not even reward cycle 0 is complete.
+    pub fn get_highest_complete_reward_cycle(
+        &self,
+        burnchain: &Burnchain,
+    ) -> Result<Option<u64>, DBError> {
+        let tip_height = match self.get_canonical_chain_tip() {
+            Ok(tip) => tip.block_height,
+            Err(BurnchainError::DBError(e)) => return Err(e),
+            Err(_) => return Ok(None),
+        };
+        // reward cycle N's last block (the one before reward cycle N + 1's first block) is the
+        // lowest height that `block_height_to_reward_cycle()` maps to N + 1
+        Ok(burnchain
+            .block_height_to_reward_cycle(tip_height)
+            .and_then(|reward_cycle| reward_cycle.checked_sub(1)))
+    }
+
+    /// Is `txid` the txid of a stored block-commit?  Unlike `get_burnchain_op()`, this
+    /// surfaces DB errors instead of treating them as a missing op.
+    pub fn is_block_commit(&self, txid: &Txid) -> Result<bool, DBError> {
+        let qry = "SELECT op FROM burnchain_db_block_ops WHERE txid = ?1";
+        let ops: Vec<BlockstackOperationType> = query_rows(&self.conn, qry, &[txid])?;
+        Ok(ops
+            .iter()
+            .any(|op| matches!(op, BlockstackOperationType::LeaderBlockCommit(_))))
+    }
+
     pub fn get_burnchain_op(&self, txid: &Txid) -> Option<BlockstackOperationType> {
         let qry = "SELECT op FROM burnchain_db_block_ops WHERE txid = ?";
 
@@ -314,12 +1295,14 @@ impl BurnchainDB {
     }
 
     /// Filter out the burnchain block's transactions that could be blockstack transactions.
-    /// Return the ordered list of blockstack operations by vtxindex
+    /// Return the ordered list of blockstack operations by vtxindex.  If `dropped_txs` is given,
+    /// each transaction that could not be classified is recorded in it, with the reason why.
     fn get_blockstack_transactions(
         &self,
         burnchain: &Burnchain,
         block: &BurnchainBlock,
         block_header: &BurnchainBlockHeader,
+        mut dropped_txs: Option<&mut Vec<DroppedTx>>,
     ) -> Vec<BlockstackOperationType> {
         debug!(
             "Extract Blockstack transactions from block {} {}",
@@ -332,12 +1315,20 @@ impl BurnchainDB {
 
         for tx in block.txs().iter() {
             let result =
-                Burnchain::classify_transaction(burnchain, self, block_header, &tx, &pre_stx_ops);
-            if let Some(classified_tx) = result {
-                if let BlockstackOperationType::PreStx(pre_stx_op) = classified_tx {
+                self.tx_classifier
+                    .classify(burnchain, self, block_header, &tx, &pre_stx_ops);
+            match result {
+                Ok(BlockstackOperationType::PreStx(pre_stx_op)) => {
                     pre_stx_ops.insert(pre_stx_op.txid.clone(), pre_stx_op);
-                } else {
-                    ops.push(classified_tx);
+                }
+                Ok(classified_tx) => ops.push(classified_tx),
+                Err(reason) => {
+                    if let Some(dropped_txs) = dropped_txs.as_mut() {
+                        dropped_txs.push(DroppedTx {
+                            txid: tx.txid(),
+                            reason,
+                        });
+                    }
                 }
             }
         }
@@ -353,25 +1344,71 @@ impl BurnchainDB {
         ops
     }
 
+    /// Install (or with `None`, remove) a filter that every blockstack op must pass in order to
+    /// be stored.  Ops it rejects are dropped from each new block, and are not returned by
+    /// `store_new_burnchain_block()`.
+    pub fn set_op_filter(&mut self, op_filter: Option<BlockstackOpFilter>) {
+        self.op_filter = op_filter;
+    }
+
+    /// Replace the classifier that turns each new block's transactions into blockstack ops
+    pub fn set_tx_classifier(&mut self, tx_classifier: Box<dyn BurnchainTxClassifier>) {
+        self.tx_classifier = tx_classifier;
+    }
+
     pub fn store_new_burnchain_block(
         &mut self,
         burnchain: &Burnchain,
         block: &BurnchainBlock,
     ) -> Result<Vec<BlockstackOperationType>, BurnchainError> {
-        let header = block.header();
-        debug!("Storing new burnchain block";
-              "burn_header_hash" => %header.block_hash.to_string());
-        let mut blockstack_ops = self.get_blockstack_transactions(burnchain, block, &header);
-        apply_blockstack_txs_safety_checks(header.block_height, &mut blockstack_ops);
+        self.store_block_and_ops(burnchain, block, None)
+    }
 
-        let db_tx = self.tx_begin()?;
+    /// Like `store_new_burnchain_block()`, but also records in `dropped_txs` each of the block's
+    /// transactions that could not be classified as a blockstack op, and why.
+    pub fn store_new_burnchain_block_with_diagnostics(
+        &mut self,
+        burnchain: &Burnchain,
+        block: &BurnchainBlock,
+        dropped_txs: &mut Vec<DroppedTx>,
+    ) -> Result<Vec<BlockstackOperationType>, BurnchainError> {
+        self.store_block_and_ops(burnchain, block, Some(dropped_txs))
+    }
+
+    fn store_block_and_ops(
+        &mut self,
+        burnchain: &Burnchain,
+        block: &BurnchainBlock,
+        dropped_txs: Option<&mut Vec<DroppedTx>>,
+    ) -> Result<Vec<BlockstackOperationType>, BurnchainError> {
+        let header = block.header();
+        debug!("Storing new burnchain block";
+              "burn_header_hash" => %header.block_hash.to_string());
+        let mut blockstack_ops =
+            self.get_blockstack_transactions(burnchain, block, &header, dropped_txs);
+        apply_blockstack_txs_safety_checks(header.block_height, &mut blockstack_ops);
 
-        db_tx.store_burnchain_db_entry(&header)?;
-        db_tx.store_blockstack_ops(&header.block_hash, &blockstack_ops)?;
+        self.with_write_tx(|db_tx| {
+            db_tx.store_burnchain_db_entry(&header)?;
+            db_tx.store_blockstack_ops(&header.block_hash, &blockstack_ops)
+        })
+    }
 
-        db_tx.commit()?;
+    /// Delete a leaf burnchain block and its ops (see `BurnchainDBTransaction::delete_burnchain_block()`)
+    pub fn delete_burnchain_block(
+        &mut self,
+        block_hash: &BurnchainHeaderHash,
+    ) -> Result<(), BurnchainError> {
+        self.with_write_tx(|db_tx| db_tx.delete_burnchain_block(block_hash))
+    }
 
-        Ok(blockstack_ops)
+    /// Delete every block in a reward cycle (see `BurnchainDBTransaction::purge_reward_cycle()`)
+    pub fn purge_reward_cycle(
+        &mut self,
+        burnchain: &Burnchain,
+        reward_cycle: u64,
+    ) -> Result<(), BurnchainError> {
+        self.with_write_tx(|db_tx| db_tx.purge_reward_cycle(burnchain, reward_cycle))
     }
 
     #[cfg(test)]
@@ -382,14 +1419,11 @@ impl BurnchainDB {
     ) -> Result<(), BurnchainError> {
         apply_blockstack_txs_safety_checks(header.block_height, &mut blockstack_ops);
 
-        let db_tx = self.tx_begin()?;
-
-        db_tx.store_burnchain_db_entry(&header)?;
-        db_tx.store_blockstack_ops(&header.block_hash, &blockstack_ops)?;
-
-        db_tx.commit()?;
-
-        Ok(())
+        self.with_write_tx(|db_tx| {
+            db_tx.store_burnchain_db_entry(&header)?;
+            db_tx.store_blockstack_ops(&header.block_hash, &blockstack_ops)?;
+            Ok(())
+        })
     }
 }
 
@@ -401,15 +1435,24 @@ mod tests {
     use crate::burnchains::bitcoin::address::*;
     use crate::burnchains::bitcoin::blocks::*;
     use crate::burnchains::bitcoin::*;
+    use crate::burnchains::BurnchainSigner;
     use crate::burnchains::PoxConstants;
     use crate::burnchains::BLOCKSTACK_MAGIC_MAINNET;
+    use crate::chainstate::burn::operations::{LeaderKeyRegisterOp, PreStxOp};
     use crate::chainstate::burn::*;
     use crate::chainstate::stacks::*;
     use stacks_common::deps_common::bitcoin::blockdata::transaction::Transaction as BtcTx;
     use stacks_common::deps_common::bitcoin::network::serialize::deserialize;
     use stacks_common::util::hash::*;
+    use stacks_common::util::vrf::VRFPublicKey;
 
     use crate::types::chainstate::StacksAddress;
+    use crate::types::chainstate::{BlockHeaderHash, VRFSeed};
+    use stacks_common::address::AddressHashMode;
+
+    use std::sync::{Arc, Mutex};
+
+    use rand::{thread_rng, Rng};
 
     use super::*;
 
@@ -418,6 +1461,68 @@ mod tests {
         deserialize(&tx_bin.to_vec()).unwrap()
     }
 
+    fn make_test_header(
+        block_height: u64,
+        block_hash: &BurnchainHeaderHash,
+        parent_block_hash: &BurnchainHeaderHash,
+    ) -> BurnchainBlockHeader {
+        BurnchainBlockHeader {
+            block_height,
+            block_hash: block_hash.clone(),
+            parent_block_hash: parent_block_hash.clone(),
+            num_txs: 0,
+            timestamp: block_height,
+        }
+    }
+
+    fn make_block_commit_op(
+        txid_byte: u8,
+        vtxindex: u32,
+        burn_fee: u64,
+        header: &BurnchainBlockHeader,
+    ) -> BlockstackOperationType {
+        BlockstackOperationType::LeaderBlockCommit(LeaderBlockCommitOp {
+            block_header_hash: BlockHeaderHash([txid_byte; 32]),
+            new_seed: VRFSeed([txid_byte; 32]),
+            parent_block_ptr: 0,
+            parent_vtxindex: 0,
+            key_block_ptr: 1,
+            key_vtxindex: 0,
+            memo: vec![],
+            burn_fee,
+            input: (Txid([txid_byte; 32]), 0),
+            burn_parent_modulus: 0,
+            apparent_sender: BurnchainSigner {
+                hash_mode: AddressHashMode::SerializeP2PKH,
+                num_sigs: 1,
+                public_keys: vec![],
+            },
+            commit_outs: vec![],
+            sunset_burn: 0,
+            txid: Txid([txid_byte; 32]),
+            vtxindex,
+            block_height: header.block_height,
+            burn_header_hash: header.block_hash.clone(),
+        })
+    }
+
+    fn make_pre_stx_op(
+        txid_byte: u8,
+        vtxindex: u32,
+        header: &BurnchainBlockHeader,
+    ) -> BlockstackOperationType {
+        BlockstackOperationType::PreStx(PreStxOp {
+            output: StacksAddress {
+                version: 26,
+                bytes: Hash160([txid_byte; 20]),
+            },
+            txid: Txid([txid_byte; 32]),
+            vtxindex,
+            block_height: header.block_height,
+            burn_header_hash: header.block_hash.clone(),
+        })
+    }
+
     #[test]
     fn test_store_and_fetch() {
         let first_bhh = BurnchainHeaderHash([0; 32]);
@@ -761,4 +1866,1640 @@ mod tests {
             panic!("EXPECTED to parse a stack stx op");
         }
     }
+
+    #[test]
+    fn test_store_with_diagnostics() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let mut burnchain = Burnchain::regtest(":memory:");
+        burnchain.pox_constants = PoxConstants::test_default();
+        burnchain.pox_constants.sunset_start = 999;
+        burnchain.pox_constants.sunset_end = 1000;
+
+        let make_tx = |txid_byte: u8, vtxindex: u32, opcode: u8, data_len: usize, input: Txid| {
+            BitcoinTransaction {
+                txid: Txid([txid_byte; 32]),
+                vtxindex,
+                opcode,
+                data: vec![1; data_len],
+                data_amt: 0,
+                inputs: vec![BitcoinTxInput {
+                    keys: vec![],
+                    num_required: 0,
+                    in_type: BitcoinInputType::Standard,
+                    tx_ref: (input, 1),
+                }],
+                outputs: vec![BitcoinTxOutput {
+                    units: 10,
+                    address: BitcoinAddress {
+                        addrtype: BitcoinAddressType::PublicKeyHash,
+                        network_id: BitcoinNetworkType::Mainnet,
+                        bytes: Hash160([1; 20]),
+                    },
+                }],
+            }
+        };
+
+        let pre_stx_txid = Txid([5; 32]);
+        let txs = vec![
+            make_tx(5, 0, Opcodes::PreStx as u8, 80, Txid([0; 32])),
+            // valid
+            make_tx(6, 1, Opcodes::StackStx as u8, 80, pre_stx_txid.clone()),
+            // too short to hold a stack-stx payload
+            make_tx(7, 2, Opcodes::StackStx as u8, 3, pre_stx_txid.clone()),
+            // spends an unknown pre-stx
+            make_tx(8, 3, Opcodes::StackStx as u8, 80, Txid([9; 32])),
+            // not an opcode at all
+            make_tx(10, 4, b'z', 80, Txid([0; 32])),
+        ];
+        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            2,
+            &BurnchainHeaderHash([2; 32]),
+            &first_bhh,
+            &txs,
+            350,
+        ));
+
+        let mut dropped_txs = vec![];
+        let ops = burnchain_db
+            .store_new_burnchain_block_with_diagnostics(&burnchain, &block, &mut dropped_txs)
+            .unwrap();
+        assert_eq!(
+            ops.iter().map(|op| op.txid()).collect::<Vec<_>>(),
+            vec![Txid([5; 32]), Txid([6; 32])]
+        );
+        assert_eq!(
+            dropped_txs,
+            vec![
+                DroppedTx {
+                    txid: Txid([7; 32]),
+                    reason: DroppedTxReason::MalformedData,
+                },
+                DroppedTx {
+                    txid: Txid([8; 32]),
+                    reason: DroppedTxReason::MissingPreStx,
+                },
+                DroppedTx {
+                    txid: Txid([10; 32]),
+                    reason: DroppedTxReason::NotBlockstackOp,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_custom_tx_classifier() {
+        /// Classifies every transaction with opcode `x` as a block commit, and nothing else
+        struct StubClassifier;
+
+        impl BurnchainTxClassifier for StubClassifier {
+            fn classify(
+                &self,
+                _burnchain: &Burnchain,
+                _burnchain_db: &BurnchainDB,
+                block_header: &BurnchainBlockHeader,
+                burn_tx: &BurnchainTransaction,
+                _pre_stx_op_map: &HashMap<Txid, PreStxOp>,
+            ) -> Result<BlockstackOperationType, DroppedTxReason> {
+                if burn_tx.opcode() != b'x' {
+                    return Err(DroppedTxReason::NotBlockstackOp);
+                }
+                Ok(make_block_commit_op(
+                    burn_tx.txid().0[0],
+                    burn_tx.vtxindex(),
+                    1000,
+                    block_header,
+                ))
+            }
+        }
+
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+        burnchain_db.set_tx_classifier(Box::new(StubClassifier));
+
+        let burnchain = Burnchain::regtest(":memory:");
+        let make_tx = |txid_byte: u8, vtxindex: u32, opcode: u8| BitcoinTransaction {
+            txid: Txid([txid_byte; 32]),
+            vtxindex,
+            opcode,
+            data: vec![],
+            data_amt: 0,
+            inputs: vec![],
+            outputs: vec![],
+        };
+        let txs = vec![
+            make_tx(1, 0, b'x'),
+            // a valid bitcoin-encoded pre-stx opcode, which the stub doesn't recognize
+            make_tx(2, 1, Opcodes::PreStx as u8),
+            make_tx(3, 2, b'x'),
+        ];
+        let block = BurnchainBlock::Bitcoin(BitcoinBlock::new(
+            2,
+            &BurnchainHeaderHash([2; 32]),
+            &first_bhh,
+            &txs,
+            350,
+        ));
+        let header = block.header();
+
+        let mut dropped_txs = vec![];
+        let ops = burnchain_db
+            .store_new_burnchain_block_with_diagnostics(&burnchain, &block, &mut dropped_txs)
+            .unwrap();
+        let summarize = |ops: &[BlockstackOperationType]| -> Vec<(Txid, u32, u64)> {
+            ops.iter()
+                .map(|op| match op {
+                    BlockstackOperationType::LeaderBlockCommit(commit) => {
+                        (commit.txid.clone(), commit.vtxindex, commit.burn_fee)
+                    }
+                    _ => panic!("Unexpected op: {:?}", op),
+                })
+                .collect()
+        };
+        let expected = vec![(Txid([1; 32]), 0, 1000), (Txid([3; 32]), 2, 1000)];
+        assert_eq!(summarize(&ops), expected);
+        assert_eq!(
+            summarize(
+                &burnchain_db
+                    .get_burnchain_block(&header.block_hash)
+                    .unwrap()
+                    .ops
+            ),
+            expected
+        );
+        assert_eq!(
+            dropped_txs,
+            vec![DroppedTx {
+                txid: Txid([2; 32]),
+                reason: DroppedTxReason::NotBlockstackOp,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_canonical_chain_tip_tiebreak() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let first_timestamp = 321;
+        let first_height = 1;
+
+        let mut burnchain_db =
+            BurnchainDB::connect(":memory:", first_height, &first_bhh, first_timestamp, true)
+                .unwrap();
+
+        let low_hash = BurnchainHeaderHash([1; 32]);
+        let high_hash = BurnchainHeaderHash([2; 32]);
+
+        for hash in [&high_hash, &low_hash].iter() {
+            let header = BurnchainBlockHeader {
+                block_height: first_height + 1,
+                block_hash: (*hash).clone(),
+                parent_block_hash: first_bhh.clone(),
+                num_txs: 0,
+                timestamp: first_timestamp + 1,
+            };
+            burnchain_db
+                .raw_store_burnchain_block(header, vec![])
+                .unwrap();
+        }
+
+        let tip = burnchain_db.get_canonical_chain_tip().unwrap();
+        assert_eq!(tip.block_hash, low_hash);
+
+        let tip = burnchain_db
+            .get_canonical_chain_tip_with_tiebreak(TipTiebreak::HashAsc)
+            .unwrap();
+        assert_eq!(tip.block_hash, low_hash);
+
+        let tip = burnchain_db
+            .get_canonical_chain_tip_with_tiebreak(TipTiebreak::HashDesc)
+            .unwrap();
+        assert_eq!(tip.block_hash, high_hash);
+    }
+
+    #[test]
+    fn test_connect_with_opts() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+
+        let burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+        let synchronous: i64 = burnchain_db
+            .conn
+            .query_row("PRAGMA synchronous", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(synchronous, 1);
+
+        let opts = BurnchainDBOpenOpts {
+            synchronous: "FULL".to_string(),
+            mmap_size: Some(1 << 20),
+            ..BurnchainDBOpenOpts::default()
+        };
+        let burnchain_db =
+            BurnchainDB::connect_with_opts(":memory:", 1, &first_bhh, 321, true, &opts).unwrap();
+        let synchronous: i64 = burnchain_db
+            .conn
+            .query_row("PRAGMA synchronous", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(synchronous, 2);
+
+        let opts = BurnchainDBOpenOpts {
+            synchronous: "NORMAL".to_string(),
+            ..BurnchainDBOpenOpts::default()
+        };
+        let burnchain_db =
+            BurnchainDB::connect_with_opts(":memory:", 1, &first_bhh, 321, true, &opts).unwrap();
+        let synchronous: i64 = burnchain_db
+            .conn
+            .query_row("PRAGMA synchronous", NO_PARAMS, |row| row.get(0))
+            .unwrap();
+        assert_eq!(synchronous, 1);
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_hash,
+            first_bhh
+        );
+    }
+
+    #[test]
+    fn test_iter_blockstack_ops() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // canonical fork: 1 <- 2 <- 3 <- 4, and a non-canonical sibling of block 3
+        let hashes: Vec<_> = (2..5).map(|i| BurnchainHeaderHash([i; 32])).collect();
+        let mut parent = first_bhh.clone();
+        for (i, hash) in hashes.iter().enumerate() {
+            let header = make_test_header(i as u64 + 2, hash, &parent);
+            let base = 0x10 * (i as u8 + 1);
+            let ops = vec![
+                make_pre_stx_op(base + 2, 5, &header),
+                make_pre_stx_op(base + 1, 1, &header),
+            ];
+            burnchain_db.raw_store_burnchain_block(header, ops).unwrap();
+            parent = hash.clone();
+        }
+
+        let fork_header = make_test_header(3, &BurnchainHeaderHash([0xff; 32]), &hashes[0]);
+        let fork_ops = vec![make_pre_stx_op(0xf0, 0, &fork_header)];
+        burnchain_db
+            .raw_store_burnchain_block(fork_header, fork_ops)
+            .unwrap();
+
+        let replayed: Vec<_> = burnchain_db
+            .iter_blockstack_ops(0, 10)
+            .map(|res| {
+                let (height, op) = res.unwrap();
+                (height, op.vtxindex(), op.txid())
+            })
+            .collect();
+
+        assert_eq!(
+            replayed,
+            vec![
+                (2, 1, Txid([0x11; 32])),
+                (2, 5, Txid([0x12; 32])),
+                (3, 1, Txid([0x21; 32])),
+                (3, 5, Txid([0x22; 32])),
+                (4, 1, Txid([0x31; 32])),
+                (4, 5, Txid([0x32; 32])),
+            ]
+        );
+
+        let replayed: Vec<_> = burnchain_db
+            .iter_blockstack_ops(3, 4)
+            .map(|res| res.unwrap().1.txid())
+            .collect();
+        assert_eq!(replayed, vec![Txid([0x21; 32]), Txid([0x22; 32])]);
+    }
+
+    /// slog drain that records each message along with its key/value pairs
+    #[derive(Clone, Default)]
+    struct CapturingDrain {
+        records: Arc<Mutex<Vec<(String, HashMap<String, String>)>>>,
+    }
+
+    struct CapturingSerializer(HashMap<String, String>);
+
+    impl slog::Serializer for CapturingSerializer {
+        fn emit_arguments(&mut self, key: slog::Key, val: &std::fmt::Arguments) -> slog::Result {
+            self.0.insert(key.to_string(), val.to_string());
+            Ok(())
+        }
+    }
+
+    impl slog::Drain for CapturingDrain {
+        type Ok = ();
+        type Err = slog::Never;
+
+        fn log(
+            &self,
+            record: &slog::Record,
+            values: &slog::OwnedKVList,
+        ) -> Result<(), slog::Never> {
+            use slog::KV;
+            let mut serializer = CapturingSerializer(HashMap::new());
+            record.kv().serialize(record, &mut serializer).unwrap();
+            values.serialize(record, &mut serializer).unwrap();
+            self.records
+                .lock()
+                .unwrap()
+                .push((record.msg().to_string(), serializer.0));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_structured_write_logging() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let drain = CapturingDrain::default();
+        let log = Logger::root(drain.clone(), o!("component" => "burnchain_db"));
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![make_pre_stx_op(0x11, 3, &header)];
+
+        let db_tx = burnchain_db.tx_begin_with_logger(log).unwrap();
+        db_tx.store_burnchain_db_entry(&header).unwrap();
+        db_tx
+            .store_blockstack_ops(&header.block_hash, &ops)
+            .unwrap();
+        db_tx.commit().unwrap();
+
+        let records = drain.records.lock().unwrap();
+        assert_eq!(records.len(), 2);
+
+        let (msg, fields) = &records[0];
+        assert_eq!(msg, "Store burnchain block header");
+        assert_eq!(fields["component"], "burnchain_db");
+        assert_eq!(fields["block_height"], "2");
+        assert_eq!(fields["burn_header_hash"], header.block_hash.to_string());
+        assert_eq!(fields["parent_burn_header_hash"], first_bhh.to_string());
+        assert_eq!(fields["num_txs"], "0");
+
+        let (msg, fields) = &records[1];
+        assert_eq!(msg, "Store blockstack op");
+        assert_eq!(fields["block_height"], "2");
+        assert_eq!(fields["burn_header_hash"], header.block_hash.to_string());
+        assert_eq!(fields["txid"], Txid([0x11; 32]).to_string());
+        assert_eq!(fields["vtxindex"], "3");
+    }
+
+    fn make_busy_error() -> BurnchainError {
+        BurnchainError::from(sqlite_error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_BUSY),
+            None,
+        ))
+    }
+
+    #[test]
+    fn test_retry_on_busy() {
+        // transient busy errors are retried until the call succeeds
+        let mut calls = 0;
+        let res = retry_on_busy(3, 1, || {
+            calls += 1;
+            if calls < 3 {
+                Err(make_busy_error())
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(res.unwrap(), 3);
+
+        // ...but only up to the maximum number of attempts
+        let mut calls = 0;
+        let res: Result<(), _> = retry_on_busy(2, 1, || {
+            calls += 1;
+            Err(make_busy_error())
+        });
+        assert!(is_busy_error(&res.unwrap_err()));
+        assert_eq!(calls, 2);
+
+        // other errors are fatal
+        let mut calls = 0;
+        let res: Result<(), _> = retry_on_busy(3, 1, || {
+            calls += 1;
+            Err(BurnchainError::from(DBError::Corruption))
+        });
+        assert!(!is_busy_error(&res.unwrap_err()));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn test_store_under_contention() {
+        let path = format!(
+            "/tmp/stacks-node-tests/unit-tests-burnchain-db/contention-{}.sqlite",
+            to_hex(&thread_rng().gen::<[u8; 16]>())
+        );
+        fs::create_dir_all("/tmp/stacks-node-tests/unit-tests-burnchain-db").unwrap();
+
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(&path, 1, &first_bhh, 321, true).unwrap();
+
+        // a second handle holds the write lock for a while
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let other_path = path.clone();
+        let holder = std::thread::spawn(move || {
+            let mut other_db = BurnchainDB::open(&other_path, true).unwrap();
+            let db_tx = other_db.tx_begin().unwrap();
+            locked_tx.send(()).unwrap();
+            sleep_ms(500);
+            db_tx.commit().unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![make_pre_stx_op(0x11, 1, &header)];
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), ops)
+            .unwrap();
+
+        holder.join().unwrap();
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_hash,
+            header.block_hash
+        );
+        assert!(burnchain_db.get_burnchain_op(&Txid([0x11; 32])).is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_busy_write_is_retried() {
+        let path = format!(
+            "/tmp/stacks-node-tests/unit-tests-burnchain-db/busy-retry-{}.sqlite",
+            to_hex(&thread_rng().gen::<[u8; 16]>())
+        );
+        fs::create_dir_all("/tmp/stacks-node-tests/unit-tests-burnchain-db").unwrap();
+
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        BurnchainDB::connect(&path, 1, &first_bhh, 321, true).unwrap();
+
+        // a second handle holds the write lock for `hold_ms`
+        let hold_lock = |hold_ms: u64| {
+            let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+            let other_path = path.clone();
+            let holder = std::thread::spawn(move || {
+                let mut other_db = BurnchainDB::open(&other_path, true).unwrap();
+                let db_tx = other_db.tx_begin().unwrap();
+                locked_tx.send(()).unwrap();
+                sleep_ms(hold_ms);
+                db_tx.commit().unwrap();
+            });
+            locked_rx.recv().unwrap();
+            holder
+        };
+
+        // by default, the write waits in tx_busy_handler and goes through once the lock is
+        // released, however long that takes
+        let mut burnchain_db =
+            BurnchainDB::open_with_opts(&path, true, &BurnchainDBOpenOpts::default()).unwrap();
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let holder = hold_lock(5000);
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), vec![])
+            .unwrap();
+        holder.join().unwrap();
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_hash,
+            header.block_hash
+        );
+
+        // with retries opted into, the write is retried and goes through once the lock is
+        // released, even though the first attempt outlasts the bounded busy handler
+        let opts = BurnchainDBOpenOpts {
+            write_attempts: 5,
+            ..BurnchainDBOpenOpts::default()
+        };
+        let mut burnchain_db = BurnchainDB::open_with_opts(&path, true, &opts).unwrap();
+        let header = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header.block_hash);
+        let holder = hold_lock(2500);
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), vec![])
+            .unwrap();
+        holder.join().unwrap();
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_hash,
+            header.block_hash
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_busy_timeout() {
+        let path = format!(
+            "/tmp/stacks-node-tests/unit-tests-burnchain-db/busy-timeout-{}.sqlite",
+            to_hex(&thread_rng().gen::<[u8; 16]>())
+        );
+        fs::create_dir_all("/tmp/stacks-node-tests/unit-tests-burnchain-db").unwrap();
+
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let opts = BurnchainDBOpenOpts {
+            busy_timeout_ms: Some(100),
+            write_attempts: 1,
+            ..BurnchainDBOpenOpts::default()
+        };
+        let mut burnchain_db =
+            BurnchainDB::connect_with_opts(&path, 1, &first_bhh, 321, true, &opts).unwrap();
+
+        // a second handle holds the write lock for much longer than the timeout
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let (done_tx, done_rx) = std::sync::mpsc::channel::<()>();
+        let other_path = path.clone();
+        let holder = std::thread::spawn(move || {
+            let mut other_db = BurnchainDB::open(&other_path, true).unwrap();
+            let db_tx = other_db.tx_begin().unwrap();
+            locked_tx.send(()).unwrap();
+            let _ = done_rx.recv_timeout(Duration::from_secs(10));
+            db_tx.commit().unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let start = std::time::Instant::now();
+        let res = burnchain_db.raw_store_burnchain_block(header.clone(), vec![]);
+        let elapsed = start.elapsed();
+        done_tx.send(()).unwrap();
+        holder.join().unwrap();
+
+        // gave up with a busy error once the timeout passed, without waiting for the lock
+        assert!(is_busy_error(&res.unwrap_err()));
+        assert!(elapsed >= Duration::from_millis(100));
+        assert!(elapsed < Duration::from_secs(5));
+
+        // with the lock released, the same write goes through
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), vec![])
+            .unwrap();
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_hash,
+            header.block_hash
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_get_commits_at_height() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            make_block_commit_op(0x13, 7, 300, &header),
+            make_pre_stx_op(0x10, 2, &header),
+            make_block_commit_op(0x11, 3, 100, &header),
+            make_block_commit_op(0x12, 5, 200, &header),
+        ];
+        burnchain_db.raw_store_burnchain_block(header, ops).unwrap();
+
+        // a competing sibling block that loses the tiebreak
+        let fork_header = make_test_header(2, &BurnchainHeaderHash([3; 32]), &first_bhh);
+        let fork_ops = vec![make_block_commit_op(0x20, 1, 400, &fork_header)];
+        burnchain_db
+            .raw_store_burnchain_block(fork_header, fork_ops)
+            .unwrap();
+
+        let commits = burnchain_db.get_commits_at_height(2).unwrap();
+        assert_eq!(
+            commits
+                .iter()
+                .map(|commit| (commit.vtxindex, commit.txid.clone(), commit.burn_fee))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, Txid([0x11; 32]), 100),
+                (5, Txid([0x12; 32]), 200),
+                (7, Txid([0x13; 32]), 300),
+            ]
+        );
+
+        assert!(burnchain_db.get_commits_at_height(1).unwrap().is_empty());
+        assert!(burnchain_db.get_commits_at_height(3).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_burnchain_op_at() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            make_pre_stx_op(0x11, 1, &header),
+            make_block_commit_op(0x12, 4, 100, &header),
+            make_pre_stx_op(0x13, 9, &header),
+        ];
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), ops)
+            .unwrap();
+
+        let op = burnchain_db
+            .get_burnchain_op_at(&header.block_hash, 4)
+            .unwrap()
+            .unwrap();
+        assert_eq!(op.txid(), Txid([0x12; 32]));
+        assert_eq!(op.vtxindex(), 4);
+
+        let op = burnchain_db
+            .get_burnchain_op_at(&header.block_hash, 9)
+            .unwrap()
+            .unwrap();
+        assert_eq!(op.txid(), Txid([0x13; 32]));
+
+        assert!(burnchain_db
+            .get_burnchain_op_at(&header.block_hash, 2)
+            .unwrap()
+            .is_none());
+        assert!(burnchain_db
+            .get_burnchain_op_at(&first_bhh, 4)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_migrate_schema_2() {
+        let path = format!(
+            "/tmp/stacks-node-tests/unit-tests-burnchain-db/migrate-{}.sqlite",
+            to_hex(&thread_rng().gen::<[u8; 16]>())
+        );
+        fs::create_dir_all("/tmp/stacks-node-tests/unit-tests-burnchain-db").unwrap();
+
+        // make a version-1 DB by hand
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            make_pre_stx_op(0x11, 3, &header),
+            make_pre_stx_op(0x12, 7, &header),
+            make_block_commit_op(0x13, 9, 100, &header),
+        ];
+        {
+            let conn = sqlite_open(
+                &path,
+                OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_CREATE,
+                true,
+            )
+            .unwrap();
+            conn.execute_batch(BURNCHAIN_DB_INITIAL_SCHEMA).unwrap();
+            conn.execute("INSERT INTO db_config (version) VALUES (?1)", &["1"])
+                .unwrap();
+            conn.execute(
+                "INSERT INTO burnchain_db_block_headers
+                 (block_height, block_hash, parent_block_hash, num_txs, timestamp)
+                 VALUES (?1, ?2, ?3, 0, 0)",
+                &[
+                    &1i64 as &dyn ToSql,
+                    &first_bhh,
+                    &BurnchainHeaderHash::sentinel(),
+                ],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO burnchain_db_block_headers
+                 (block_height, block_hash, parent_block_hash, num_txs, timestamp)
+                 VALUES (?1, ?2, ?3, 0, 0)",
+                &[&2i64 as &dyn ToSql, &header.block_hash, &first_bhh],
+            )
+            .unwrap();
+            for op in ops.iter() {
+                conn.execute(
+                    "INSERT INTO burnchain_db_block_ops (block_hash, txid, op) VALUES (?1, ?2, ?3)",
+                    &[
+                        &header.block_hash as &dyn ToSql,
+                        op.txid_ref(),
+                        &serde_json::to_string(op).unwrap(),
+                    ],
+                )
+                .unwrap();
+            }
+        }
+
+        // can't be used read-only until migrated
+        match BurnchainDB::open(&path, false) {
+            Err(BurnchainError::DBError(DBError::OldSchema(1))) => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Opened a stale DB read-only"),
+        }
+
+        let burnchain_db = BurnchainDB::open(&path, true).unwrap();
+        assert_eq!(
+            BurnchainDB::get_schema_version(&burnchain_db.conn).unwrap(),
+            Some(BURNCHAIN_DB_VERSION.to_string())
+        );
+        let op = burnchain_db
+            .get_burnchain_op_at(&header.block_hash, 7)
+            .unwrap()
+            .unwrap();
+        assert_eq!(op.txid(), Txid([0x12; 32]));
+        let op = burnchain_db
+            .get_burnchain_op_at(&header.block_hash, 3)
+            .unwrap()
+            .unwrap();
+        assert_eq!(op.txid(), Txid([0x11; 32]));
+        let commits = burnchain_db
+            .get_commits_by_input(&Txid([0x13; 32]), 0)
+            .unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].txid, Txid([0x13; 32]));
+
+        BurnchainDB::open(&path, false).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_backup_to() {
+        let dir = "/tmp/stacks-node-tests/unit-tests-burnchain-db";
+        let suffix = to_hex(&thread_rng().gen::<[u8; 16]>());
+        let path = format!("{}/backup-src-{}.sqlite", dir, suffix);
+        let backup_path = format!("{}/backup-dest-{}.sqlite", dir, suffix);
+        fs::create_dir_all(dir).unwrap();
+
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(&path, 1, &first_bhh, 321, true).unwrap();
+
+        let header_2 = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        burnchain_db
+            .raw_store_burnchain_block(
+                header_2.clone(),
+                vec![make_block_commit_op(0x12, 4, 100, &header_2)],
+            )
+            .unwrap();
+        let header_3 = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header_2.block_hash);
+        burnchain_db
+            .raw_store_burnchain_block(header_3.clone(), vec![make_pre_stx_op(0x13, 1, &header_3)])
+            .unwrap();
+
+        burnchain_db.backup_to(&backup_path).unwrap();
+
+        // won't clobber an existing file
+        match burnchain_db.backup_to(&backup_path) {
+            Err(BurnchainError::FSError(e)) => assert_eq!(e.kind(), io::ErrorKind::AlreadyExists),
+            x => panic!("Unexpected result: {:?}", x.map(|_| ())),
+        }
+
+        let backup_db = BurnchainDB::open(&backup_path, false).unwrap();
+        assert_eq!(
+            backup_db.get_canonical_chain_tip().unwrap(),
+            burnchain_db.get_canonical_chain_tip().unwrap()
+        );
+        assert_eq!(backup_db.get_canonical_chain_tip().unwrap(), header_3);
+        let op = backup_db
+            .get_burnchain_op_at(&header_2.block_hash, 4)
+            .unwrap()
+            .unwrap();
+        assert_eq!(op.txid(), Txid([0x12; 32]));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&backup_path).unwrap();
+    }
+
+    #[test]
+    fn test_get_total_burn_in_reward_cycle() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // reward cycle 0 is blocks 2 through 11; reward cycle 1 starts at block 12
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..14u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            let mut commit = make_block_commit_op(height as u8, 2, 10 * height, &header);
+            if let BlockstackOperationType::LeaderBlockCommit(ref mut commit) = commit {
+                // not counted
+                commit.sunset_burn = 1000;
+            }
+            let ops = vec![make_pre_stx_op(0x80 + height as u8, 1, &header), commit];
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), ops)
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+
+        assert_eq!(
+            burnchain_db
+                .get_total_burn_in_reward_cycle(&burnchain, 0)
+                .unwrap(),
+            10 * (2..12).sum::<u64>()
+        );
+        assert_eq!(
+            burnchain_db
+                .get_total_burn_in_reward_cycle(&burnchain, 1)
+                .unwrap(),
+            10 * (12 + 13)
+        );
+        assert_eq!(
+            burnchain_db
+                .get_total_burn_in_reward_cycle(&burnchain, 2)
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_get_median_burn_in_reward_cycle() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // reward cycle 0 is blocks 2 through 11; reward cycle 1 starts at block 12
+        let burn_fees: &[(u64, &[u64])] = &[
+            (2, &[500, 100]),
+            (3, &[]),
+            (4, &[900]),
+            (5, &[300, 700, 200]),
+            (12, &[40, 10, 30, 20]),
+        ];
+        let mut parent_bhh = first_bhh.clone();
+        for (height, fees) in burn_fees.iter() {
+            let header = make_test_header(
+                *height,
+                &BurnchainHeaderHash([*height as u8; 32]),
+                &parent_bhh,
+            );
+            let mut ops = vec![make_pre_stx_op(0x80 + *height as u8, 0, &header)];
+            for (ix, fee) in fees.iter().enumerate() {
+                let txid_byte = (*height as u8) * 16 + ix as u8;
+                ops.push(make_block_commit_op(
+                    txid_byte,
+                    ix as u32 + 1,
+                    *fee,
+                    &header,
+                ));
+            }
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), ops)
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+
+        // fees 100, 200, 300, 500, 700, 900: the lower of the middle two
+        assert_eq!(
+            burnchain_db
+                .get_median_burn_in_reward_cycle(&burnchain, 0)
+                .unwrap(),
+            Some(300)
+        );
+        assert_eq!(
+            burnchain_db
+                .get_median_burn_in_reward_cycle(&burnchain, 1)
+                .unwrap(),
+            Some(20)
+        );
+        assert_eq!(
+            burnchain_db
+                .get_median_burn_in_reward_cycle(&burnchain, 2)
+                .unwrap(),
+            None
+        );
+
+        // an odd number of commits has a single middle
+        let header = make_test_header(13, &BurnchainHeaderHash([13; 32]), &parent_bhh);
+        burnchain_db
+            .raw_store_burnchain_block(
+                header.clone(),
+                vec![make_block_commit_op(0xfe, 1, 25, &header)],
+            )
+            .unwrap();
+        assert_eq!(
+            burnchain_db
+                .get_median_burn_in_reward_cycle(&burnchain, 1)
+                .unwrap(),
+            Some(25)
+        );
+    }
+
+    #[test]
+    fn test_get_prepare_phase_commits() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // reward cycle 0 is blocks 2 through 11, and its prepare phase is blocks 7 through 11
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..15u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            let ops = vec![
+                make_block_commit_op(height as u8, 1, 10, &header),
+                make_pre_stx_op(0x40 + height as u8, 2, &header),
+                make_block_commit_op(0x80 + height as u8, 3, 10, &header),
+            ];
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), ops)
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+
+        for height in 7..12 {
+            assert!(burnchain.is_in_prepare_phase(height));
+        }
+        assert!(!burnchain.is_in_prepare_phase(6));
+        assert!(!burnchain.is_in_prepare_phase(12));
+
+        let commits = burnchain_db
+            .get_prepare_phase_commits(&burnchain, 0)
+            .unwrap();
+        let expected: Vec<_> = (7..12u8)
+            .flat_map(|height| {
+                vec![
+                    (height as u64, 1, height),
+                    (height as u64, 3, 0x80 + height),
+                ]
+            })
+            .collect();
+        assert_eq!(
+            commits
+                .iter()
+                .map(|c| (c.block_height, c.vtxindex, c.txid.0[0]))
+                .collect::<Vec<_>>(),
+            expected
+        );
+
+        // reward cycle 1's prepare phase (blocks 17 through 21) has not been stored yet
+        assert!(burnchain_db
+            .get_prepare_phase_commits(&burnchain, 1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_highest_complete_reward_cycle() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+        assert_eq!(
+            burnchain_db
+                .get_highest_complete_reward_cycle(&burnchain)
+                .unwrap(),
+            None
+        );
+
+        // reward cycle 0 is blocks 2 through 11, and reward cycle 1 is blocks 12 through 21
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..26u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), vec![])
+                .unwrap();
+            parent_bhh = header.block_hash;
+
+            let expected = match height {
+                2..=10 => None,
+                11..=20 => Some(0),
+                _ => Some(1),
+            };
+            assert_eq!(
+                burnchain_db
+                    .get_highest_complete_reward_cycle(&burnchain)
+                    .unwrap(),
+                expected,
+                "at height {}",
+                height
+            );
+        }
+    }
+
+    #[test]
+    fn test_is_block_commit() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let leader_key = BlockstackOperationType::LeaderKeyRegister(LeaderKeyRegisterOp {
+            consensus_hash: ConsensusHash([0; 20]),
+            public_key: VRFPublicKey::from_bytes(
+                &hex_bytes("a366b51292bef4edd64063d9145c617fec373bceb0758e98cd72becd84d54c7a")
+                    .unwrap(),
+            )
+            .unwrap(),
+            memo: vec![],
+            address: StacksAddress {
+                version: 26,
+                bytes: Hash160([1; 20]),
+            },
+            txid: Txid([1; 32]),
+            vtxindex: 0,
+            block_height: header.block_height,
+            burn_header_hash: header.block_hash.clone(),
+        });
+        let ops = vec![
+            leader_key,
+            make_block_commit_op(2, 1, 100, &header),
+            make_pre_stx_op(3, 2, &header),
+        ];
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), ops)
+            .unwrap();
+
+        assert!(!burnchain_db.is_block_commit(&Txid([1; 32])).unwrap());
+        assert!(burnchain_db.is_block_commit(&Txid([2; 32])).unwrap());
+        assert!(!burnchain_db.is_block_commit(&Txid([3; 32])).unwrap());
+        assert!(!burnchain_db.is_block_commit(&Txid([4; 32])).unwrap());
+    }
+
+    #[test]
+    fn test_get_commits_by_input() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let spend = |op: BlockstackOperationType, input: (Txid, u32)| match op {
+            BlockstackOperationType::LeaderBlockCommit(mut commit) => {
+                commit.input = input;
+                BlockstackOperationType::LeaderBlockCommit(commit)
+            }
+            _ => unreachable!(),
+        };
+        let shared_input = (Txid([0xaa; 32]), 1);
+
+        let header_2 = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            spend(
+                make_block_commit_op(1, 0, 100, &header_2),
+                shared_input.clone(),
+            ),
+            spend(
+                make_block_commit_op(2, 1, 100, &header_2),
+                (Txid([0xaa; 32]), 2),
+            ),
+            make_pre_stx_op(3, 2, &header_2),
+        ];
+        burnchain_db
+            .raw_store_burnchain_block(header_2.clone(), ops)
+            .unwrap();
+
+        let header_3 = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header_2.block_hash);
+        let ops = vec![spend(
+            make_block_commit_op(4, 0, 100, &header_3),
+            shared_input.clone(),
+        )];
+        burnchain_db
+            .raw_store_burnchain_block(header_3.clone(), ops)
+            .unwrap();
+
+        let txids: Vec<_> = burnchain_db
+            .get_commits_by_input(&shared_input.0, shared_input.1)
+            .unwrap()
+            .into_iter()
+            .map(|commit| commit.txid)
+            .collect();
+        assert_eq!(txids, vec![Txid([1; 32]), Txid([4; 32])]);
+
+        let commits = burnchain_db
+            .get_commits_by_input(&Txid([0xaa; 32]), 2)
+            .unwrap();
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].txid, Txid([2; 32]));
+
+        assert!(burnchain_db
+            .get_commits_by_input(&Txid([0xbb; 32]), 1)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_validate_all_ops_deserialize() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            make_pre_stx_op(1, 0, &header),
+            make_block_commit_op(2, 1, 100, &header),
+        ];
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), ops)
+            .unwrap();
+        assert!(burnchain_db
+            .validate_all_ops_deserialize()
+            .unwrap()
+            .is_empty());
+
+        burnchain_db
+            .conn
+            .execute(
+                "INSERT INTO burnchain_db_block_ops (block_hash, txid, op, vtxindex)
+                 VALUES (?1, ?2, ?3, 2)",
+                &[
+                    &header.block_hash as &dyn ToSql,
+                    &Txid([3; 32]),
+                    &"{\"UnknownOp\":{}}",
+                ],
+            )
+            .unwrap();
+
+        let failures = burnchain_db.validate_all_ops_deserialize().unwrap();
+        assert_eq!(failures.len(), 1);
+        let (block_hash, txid, err) = &failures[0];
+        assert_eq!(block_hash, &header.block_hash);
+        assert_eq!(txid, &Txid([3; 32]));
+        assert!(err.contains("UnknownOp"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_commit_chain_reward_cycle_span() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // a commit in each of blocks 2..=35, each building on the commit in the block before it
+        let mut parent_hash = first_bhh.clone();
+        let mut commits = vec![];
+        for height in 2..=35u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_hash,
+            );
+            let mut op = make_block_commit_op(height as u8, 1, 100, &header);
+            if let BlockstackOperationType::LeaderBlockCommit(ref mut commit) = op {
+                if height > 2 {
+                    commit.parent_block_ptr = (height - 1) as u32;
+                    commit.parent_vtxindex = 1;
+                }
+                commits.push(commit.clone());
+            }
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), vec![op])
+                .unwrap();
+            parent_hash = header.block_hash;
+        }
+        let tip_commit = commits.last().unwrap();
+        let cycle_of = |height| burnchain.block_height_to_reward_cycle(height).unwrap();
+        assert_eq!(cycle_of(2), 0);
+        assert_eq!(cycle_of(35), 3);
+
+        // the whole chain, back to the commit in block 2
+        assert_eq!(
+            burnchain_db
+                .commit_chain_reward_cycle_span(&burnchain, tip_commit, 100)
+                .unwrap(),
+            4
+        );
+        // only follows `max_depth` parents: block 31 starts cycle 3, and block 21 cycle 2
+        assert_eq!(cycle_of(31), 3);
+        assert_eq!(cycle_of(21), 2);
+        for (max_depth, span) in [(0, 1), (4, 1), (5, 2), (14, 2), (15, 3)].iter() {
+            assert_eq!(
+                burnchain_db
+                    .commit_chain_reward_cycle_span(&burnchain, tip_commit, *max_depth)
+                    .unwrap(),
+                *span
+            );
+        }
+
+        // a commit whose parent is not a block-commit only spans its own cycle
+        let mut orphan = tip_commit.clone();
+        orphan.parent_vtxindex = 7;
+        assert_eq!(
+            burnchain_db
+                .commit_chain_reward_cycle_span(&burnchain, &orphan, 100)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_get_stored_heights_in_reward_cycle() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // cycle 0 is blocks 2..12, and cycle 1 is blocks 12..22
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..16u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), vec![])
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+        // a fork block's height is only reported once
+        let fork_header = make_test_header(13, &BurnchainHeaderHash([0xff; 32]), &first_bhh);
+        burnchain_db
+            .raw_store_burnchain_block(fork_header, vec![])
+            .unwrap();
+        burnchain_db
+            .conn
+            .execute(
+                "DELETE FROM burnchain_db_block_headers WHERE block_height = 14",
+                NO_PARAMS,
+            )
+            .unwrap();
+
+        assert_eq!(
+            burnchain_db
+                .get_stored_heights_in_reward_cycle(&burnchain, 0)
+                .unwrap(),
+            (2..12).collect::<Vec<u64>>()
+        );
+        assert_eq!(
+            burnchain_db
+                .get_stored_heights_in_reward_cycle(&burnchain, 1)
+                .unwrap(),
+            vec![12, 13, 15]
+        );
+        assert!(burnchain_db
+            .get_stored_heights_in_reward_cycle(&burnchain, 2)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_get_op_count() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            make_pre_stx_op(1, 0, &header),
+            make_block_commit_op(2, 1, 100, &header),
+            make_pre_stx_op(3, 2, &header),
+        ];
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), ops)
+            .unwrap();
+
+        let empty_header = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header.block_hash);
+        burnchain_db
+            .raw_store_burnchain_block(empty_header.clone(), vec![])
+            .unwrap();
+
+        assert_eq!(burnchain_db.get_op_count(&header.block_hash).unwrap(), 3);
+        assert_eq!(
+            burnchain_db.get_op_count(&empty_header.block_hash).unwrap(),
+            0
+        );
+        assert_eq!(
+            burnchain_db
+                .get_op_count(&BurnchainHeaderHash([4; 32]))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_delete_burnchain_block() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header_2 = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let header_3 = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header_2.block_hash);
+        burnchain_db
+            .raw_store_burnchain_block(header_2.clone(), vec![make_pre_stx_op(2, 0, &header_2)])
+            .unwrap();
+        burnchain_db
+            .raw_store_burnchain_block(header_3.clone(), vec![make_pre_stx_op(3, 0, &header_3)])
+            .unwrap();
+        assert_eq!(burnchain_db.get_canonical_chain_tip().unwrap(), header_3);
+
+        // blocks with children, and the first block, cannot be deleted
+        for block_hash in [&header_2.block_hash, &first_bhh].iter() {
+            match burnchain_db.delete_burnchain_block(block_hash) {
+                Err(BurnchainError::BlockNotDeletable(bhh)) => assert_eq!(&bhh, *block_hash),
+                x => panic!("Unexpected result: {:?}", x),
+            }
+        }
+        match burnchain_db.delete_burnchain_block(&BurnchainHeaderHash([9; 32])) {
+            Err(BurnchainError::UnknownBlock(_)) => {}
+            x => panic!("Unexpected result: {:?}", x),
+        }
+
+        burnchain_db
+            .delete_burnchain_block(&header_3.block_hash)
+            .unwrap();
+        assert_eq!(burnchain_db.get_canonical_chain_tip().unwrap(), header_2);
+        assert!(burnchain_db.get_burnchain_op(&Txid([3; 32])).is_none());
+        assert!(burnchain_db.get_burnchain_op(&Txid([2; 32])).is_some());
+        match burnchain_db.get_burnchain_block(&header_3.block_hash) {
+            Err(BurnchainError::UnknownBlock(_)) => {}
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Expected UnknownBlock"),
+        }
+
+        // now the parent is a leaf, and can be deleted too
+        burnchain_db
+            .delete_burnchain_block(&header_2.block_hash)
+            .unwrap();
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_hash,
+            first_bhh
+        );
+    }
+
+    #[test]
+    fn test_purge_reward_cycle() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain = Burnchain::default_unittest(1, &first_bhh);
+        burnchain.pox_constants = PoxConstants::test_default();
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        // reward cycle 0 is blocks 2 through 11, reward cycle 1 is blocks 12 through 21, and
+        // reward cycle 2 is blocks 22 through 31
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..32u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            let ops = vec![make_pre_stx_op(height as u8, 0, &header)];
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), ops)
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+        // a fork block in reward cycle 1
+        let fork_header = make_test_header(
+            13,
+            &BurnchainHeaderHash([0xff; 32]),
+            &BurnchainHeaderHash([12; 32]),
+        );
+        burnchain_db
+            .raw_store_burnchain_block(
+                fork_header.clone(),
+                vec![make_pre_stx_op(0xff, 0, &fork_header)],
+            )
+            .unwrap();
+
+        let assert_purged = |burnchain_db: &BurnchainDB, heights: &[u8]| {
+            for height in heights.iter() {
+                assert!(burnchain_db
+                    .get_burnchain_op(&Txid([*height; 32]))
+                    .is_none());
+                match burnchain_db.get_burnchain_block(&BurnchainHeaderHash([*height; 32])) {
+                    Err(BurnchainError::UnknownBlock(_)) => {}
+                    Err(e) => panic!("Unexpected error: {:?}", e),
+                    Ok(_) => panic!("Expected UnknownBlock"),
+                }
+            }
+        };
+        let assert_intact = |burnchain_db: &BurnchainDB, heights: &[u8]| {
+            for height in heights.iter() {
+                let block = burnchain_db
+                    .get_burnchain_block(&BurnchainHeaderHash([*height; 32]))
+                    .unwrap();
+                assert_eq!(block.ops.len(), 1);
+            }
+        };
+
+        // purging a middle or earlier reward cycle would orphan the blocks after it
+        for reward_cycle in 0..2 {
+            match burnchain_db.purge_reward_cycle(&burnchain, reward_cycle) {
+                Err(BurnchainError::RewardCycleNotPurgeable(x)) if x == reward_cycle => {}
+                x => panic!("Unexpected result: {:?}", x),
+            }
+        }
+        let all_heights: Vec<u8> = (2..32u8).chain(vec![0xff].into_iter()).collect();
+        assert_intact(&burnchain_db, &all_heights);
+
+        // the latest reward cycle can be purged, after which the one before it is the latest
+        burnchain_db.purge_reward_cycle(&burnchain, 2).unwrap();
+        assert_purged(&burnchain_db, &(22..32u8).collect::<Vec<_>>());
+        assert_intact(&burnchain_db, &all_heights[..20]);
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+            21
+        );
+
+        burnchain_db.purge_reward_cycle(&burnchain, 1).unwrap();
+        let cycle_1_heights: Vec<u8> = (12..22u8).chain(vec![0xff].into_iter()).collect();
+        assert_purged(&burnchain_db, &cycle_1_heights);
+        assert_intact(&burnchain_db, &all_heights[..10]);
+        assert_eq!(
+            burnchain_db.get_canonical_chain_tip().unwrap().block_height,
+            11
+        );
+
+        // purging reward cycle 0 leaves the first block behind
+        burnchain_db.purge_reward_cycle(&burnchain, 0).unwrap();
+        assert_purged(&burnchain_db, &all_heights[..10]);
+        let tip = burnchain_db.get_canonical_chain_tip().unwrap();
+        assert_eq!(tip.block_height, 1);
+        assert_eq!(tip.block_hash, first_bhh);
+    }
+
+    #[test]
+    fn test_missing_header_heights() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+        assert!(burnchain_db.missing_header_heights().unwrap().is_empty());
+
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..10u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), vec![])
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+        // a fork block doesn't fill in anything
+        let fork_header = make_test_header(5, &BurnchainHeaderHash([0xff; 32]), &first_bhh);
+        burnchain_db
+            .raw_store_burnchain_block(fork_header, vec![])
+            .unwrap();
+        assert!(burnchain_db.missing_header_heights().unwrap().is_empty());
+
+        burnchain_db
+            .conn
+            .execute(
+                "DELETE FROM burnchain_db_block_headers WHERE block_height IN (4, 7)",
+                NO_PARAMS,
+            )
+            .unwrap();
+        assert_eq!(burnchain_db.missing_header_heights().unwrap(), vec![4, 7]);
+    }
+
+    #[test]
+    fn test_get_children_headers() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let header_a = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let header_b = make_test_header(2, &BurnchainHeaderHash([1; 32]), &first_bhh);
+        let header_c = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header_a.block_hash);
+        for header in [&header_a, &header_b, &header_c].iter() {
+            burnchain_db
+                .raw_store_burnchain_block((*header).clone(), vec![])
+                .unwrap();
+        }
+
+        // forked at the first block
+        assert_eq!(
+            burnchain_db.get_children_headers(&first_bhh).unwrap(),
+            vec![header_b.clone(), header_a.clone()]
+        );
+        assert_eq!(
+            burnchain_db
+                .get_children_headers(&header_a.block_hash)
+                .unwrap(),
+            vec![header_c.clone()]
+        );
+        assert!(burnchain_db
+            .get_children_headers(&header_c.block_hash)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_verify_header_contiguity() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+        assert_eq!(burnchain_db.verify_header_contiguity().unwrap(), None);
+
+        let mut parent_bhh = first_bhh.clone();
+        for height in 2..7u64 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_bhh,
+            );
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), vec![])
+                .unwrap();
+            parent_bhh = header.block_hash;
+        }
+        // a fork off of block 3 does not break the chain
+        let fork_header = make_test_header(
+            4,
+            &BurnchainHeaderHash([0x44; 32]),
+            &BurnchainHeaderHash([3; 32]),
+        );
+        burnchain_db
+            .raw_store_burnchain_block(fork_header, vec![])
+            .unwrap();
+        assert_eq!(burnchain_db.verify_header_contiguity().unwrap(), None);
+
+        // a block whose parent is not at the height below it
+        let orphan_header = make_test_header(
+            6,
+            &BurnchainHeaderHash([0x66; 32]),
+            &BurnchainHeaderHash([0x55; 32]),
+        );
+        burnchain_db
+            .raw_store_burnchain_block(orphan_header, vec![])
+            .unwrap();
+        assert_eq!(burnchain_db.verify_header_contiguity().unwrap(), Some(6));
+
+        // a missing block
+        burnchain_db
+            .conn
+            .execute(
+                "DELETE FROM burnchain_db_block_headers WHERE block_height = 4",
+                NO_PARAMS,
+            )
+            .unwrap();
+        assert_eq!(burnchain_db.verify_header_contiguity().unwrap(), Some(4));
+    }
+
+    #[test]
+    fn test_get_parent_burnchain_block() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+
+        let mut parent_hash = first_bhh.clone();
+        for height in 2..6 {
+            let header = make_test_header(
+                height,
+                &BurnchainHeaderHash([height as u8; 32]),
+                &parent_hash,
+            );
+            let op = make_pre_stx_op(height as u8, 0, &header);
+            burnchain_db
+                .raw_store_burnchain_block(header.clone(), vec![op])
+                .unwrap();
+            parent_hash = header.block_hash;
+        }
+
+        // walk back from the tip to the first block
+        let mut cursor = burnchain_db.get_canonical_chain_tip().unwrap();
+        assert_eq!(cursor.block_height, 5);
+        while let Some(parent) = burnchain_db
+            .get_parent_burnchain_block(&cursor.block_hash)
+            .unwrap()
+        {
+            assert_eq!(parent.header.block_hash, cursor.parent_block_hash);
+            assert_eq!(parent.header.block_height + 1, cursor.block_height);
+            if parent.header.block_hash != first_bhh {
+                assert_eq!(parent.ops.len(), 1);
+                assert_eq!(
+                    parent.ops[0].txid(),
+                    Txid([parent.header.block_height as u8; 32])
+                );
+            }
+            cursor = parent.header;
+        }
+        assert_eq!(cursor.block_hash, first_bhh);
+
+        match burnchain_db.get_parent_burnchain_block(&BurnchainHeaderHash([9; 32])) {
+            Err(BurnchainError::UnknownBlock(bhh)) => assert_eq!(bhh, BurnchainHeaderHash([9; 32])),
+            Err(e) => panic!("Unexpected error: {:?}", e),
+            Ok(_) => panic!("Expected UnknownBlock"),
+        }
+    }
+
+    #[test]
+    fn test_op_filter() {
+        let first_bhh = BurnchainHeaderHash([0; 32]);
+        let mut burnchain_db = BurnchainDB::connect(":memory:", 1, &first_bhh, 321, true).unwrap();
+        burnchain_db.set_op_filter(Some(Box::new(|op| match op {
+            BlockstackOperationType::LeaderBlockCommit(commit) => commit.burn_fee >= 1000,
+            _ => true,
+        })));
+
+        let header = make_test_header(2, &BurnchainHeaderHash([2; 32]), &first_bhh);
+        let ops = vec![
+            make_block_commit_op(0x11, 1, 999, &header),
+            make_block_commit_op(0x12, 2, 1000, &header),
+            make_pre_stx_op(0x13, 3, &header),
+            make_block_commit_op(0x14, 4, 5000, &header),
+        ];
+        burnchain_db
+            .raw_store_burnchain_block(header.clone(), ops)
+            .unwrap();
+
+        let stored: Vec<_> = burnchain_db
+            .get_burnchain_block(&header.block_hash)
+            .unwrap()
+            .ops
+            .iter()
+            .map(|op| op.txid())
+            .collect();
+        assert_eq!(stored.len(), 3);
+        assert!(!stored.contains(&Txid([0x11; 32])));
+        assert!(burnchain_db.get_burnchain_op(&Txid([0x11; 32])).is_none());
+        assert!(burnchain_db.get_burnchain_op(&Txid([0x12; 32])).is_some());
+
+        // removing the filter stores everything again
+        burnchain_db.set_op_filter(None);
+        let header = make_test_header(3, &BurnchainHeaderHash([3; 32]), &header.block_hash);
+        burnchain_db
+            .raw_store_burnchain_block(
+                header.clone(),
+                vec![make_block_commit_op(0x21, 1, 1, &header)],
+            )
+            .unwrap();
+        assert!(burnchain_db.get_burnchain_op(&Txid([0x21; 32])).is_some());
+    }
 }
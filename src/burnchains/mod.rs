@@ -446,6 +446,11 @@ pub enum Error {
     UnknownBlock(BurnchainHeaderHash),
     NonCanonicalPoxId(PoxId, PoxId),
     CoordinatorClosed,
+    /// The burnchain block cannot be deleted, because it is the first block or has children
+    BlockNotDeletable(BurnchainHeaderHash),
+    /// The reward cycle's burnchain blocks cannot be purged, because blocks in later reward
+    /// cycles are still stored
+    RewardCycleNotPurgeable(u64),
 }
 
 impl fmt::Display for Error {
@@ -470,6 +475,12 @@ impl fmt::Display for Error {
                 parent, child
             ),
             Error::CoordinatorClosed => write!(f, "ChainsCoordinator channel hung up"),
+            Error::BlockNotDeletable(block) => {
+                write!(f, "Burnchain block {} cannot be deleted", block)
+            }
+            Error::RewardCycleNotPurgeable(reward_cycle) => {
+                write!(f, "Reward cycle {} cannot be purged", reward_cycle)
+            }
         }
     }
 }
@@ -492,6 +503,8 @@ impl error::Error for Error {
             Error::UnknownBlock(_) => None,
             Error::NonCanonicalPoxId(_, _) => None,
             Error::CoordinatorClosed => None,
+            Error::BlockNotDeletable(_) => None,
+            Error::RewardCycleNotPurgeable(_) => None,
         }
     }
 }
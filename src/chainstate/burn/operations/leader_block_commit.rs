@@ -832,7 +832,7 @@ impl LeaderBlockCommitOp {
                     );
                 }
             }
-            StacksEpochId::Epoch2_05 => {
+            StacksEpochId::Epoch2_05 | StacksEpochId::Epoch21 => {
                 if self.memo.len() < 1 {
                     debug!(
                         "Invalid block commit";
@@ -206,6 +206,7 @@ fn run_analysis_free<C: ClarityStorage>(
         &mut marf_kv.get_analysis_db(),
         save_contract,
         LimitedCostTracker::new_free(),
+        DEFAULT_CLI_EPOCH,
     )
 }
 
@@ -234,6 +235,7 @@ fn run_analysis<C: ClarityStorage>(
         &mut marf_kv.get_analysis_db(),
         save_contract,
         cost_track,
+        DEFAULT_CLI_EPOCH,
     )
 }
 